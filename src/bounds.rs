@@ -0,0 +1,316 @@
+use crate::mesh::{MeshData, Vertex};
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+const INF: f32 = std::f32::INFINITY;
+
+/// An axis-aligned bounding box. `min` greater than `max` means empty.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Aabb {
+            min: Point3::new(INF, INF, INF),
+            max: Point3::new(-INF, -INF, -INF),
+        }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Point3<f32>>) -> Self {
+        let mut aabb = Self::empty();
+        for p in points {
+            aabb.expand(p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    /// Half the size along each axis.
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) / 2.0
+    }
+
+    pub fn expand(&mut self, p: &Point3<f32>) {
+        for i in 0..3 {
+            self.min[i] = self.min[i].min(p[i]);
+            self.max[i] = self.max[i].max(p[i]);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut u = *self;
+        u.expand(&other.min);
+        u.expand(&other.max);
+        u
+    }
+
+    pub fn contains(&self, p: &Point3<f32>) -> bool {
+        (0..3).all(|i| p[i] >= self.min[i] && p[i] <= self.max[i])
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    /// The box around this one after an affine transform.
+    pub fn transform(&self, m: &Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = m.transform_point(&self.center());
+        let h = self.half_extents();
+        let extent = Vector3::from_fn(|r, _| (0..3).map(|c| m[(r, c)].abs() * h[c]).sum::<f32>());
+        Aabb {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Default for BoundingSphere {
+    fn default() -> Self {
+        BoundingSphere {
+            center: Point3::origin(),
+            radius: 0.0,
+        }
+    }
+}
+
+impl BoundingSphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        BoundingSphere { center, radius }
+    }
+
+    /// A sphere around `points` centred on their bounding box. Not the
+    /// smallest possible, but never more than √3 times too big.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Point3<f32>> + Clone) -> Self {
+        let aabb = Aabb::from_points(points.clone());
+        if aabb.is_empty() {
+            return Default::default();
+        }
+        let center = aabb.center();
+        let radius = points
+            .into_iter()
+            .map(|p| nalgebra::distance_squared(&center, p))
+            .fold(0.0, f32::max)
+            .sqrt();
+        BoundingSphere { center, radius }
+    }
+
+    pub fn contains(&self, p: &Point3<f32>) -> bool {
+        nalgebra::distance_squared(&self.center, p) <= self.radius * self.radius
+    }
+
+    /// The sphere around this one after an affine transform, scaled by the
+    /// transform's largest axis scale.
+    pub fn transform(&self, m: &Matrix4<f32>) -> BoundingSphere {
+        let scale = (0..3)
+            .map(|c| Vector3::new(m[(0, c)], m[(1, c)], m[(2, c)]).norm())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: m.transform_point(&self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+fn positions(vertices: &[Vertex]) -> Vec<Point3<f32>> {
+    vertices
+        .iter()
+        .map(|v| {
+            let p = v.position();
+            Point3::new(p[0], p[1], p[2])
+        })
+        .collect()
+}
+
+impl Aabb {
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        Self::from_points(positions(vertices).iter())
+    }
+}
+
+impl BoundingSphere {
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        Self::from_points(positions(vertices).iter())
+    }
+}
+
+impl MeshData {
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_vertices(&self.vertices)
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_vertices(&self.vertices)
+    }
+}
+
+/// A plane `n · p + d = 0` with `n` pointing to the inside.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub d: f32,
+}
+
+impl Plane {
+    fn from_row(row: Vector4<f32>) -> Self {
+        let normal = Vector3::new(row.x, row.y, row.z);
+        let len = normal.norm();
+        Plane {
+            normal: normal / len,
+            d: row.w / len,
+        }
+    }
+
+    /// Signed distance of `p`, positive on the inside.
+    pub fn distance(&self, p: &Point3<f32>) -> f32 {
+        self.normal.dot(&p.coords) + self.d
+    }
+}
+
+/// The six planes of a view volume, for culling.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a projection or view-projection matrix
+    /// using OpenGL's -w..w clip volume. Tests then run in the space the
+    /// matrix transforms from.
+    pub fn from_matrix(m: &Matrix4<f32>) -> Self {
+        let row = |i: usize| m.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Frustum {
+            planes: [
+                Plane::from_row(w + x),
+                Plane::from_row(w - x),
+                Plane::from_row(w + y),
+                Plane::from_row(w - y),
+                Plane::from_row(w + z),
+                Plane::from_row(w - z),
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, p: &Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| plane.distance(p) >= 0.0)
+    }
+
+    /// False only if the sphere is certainly outside.
+    pub fn intersects_sphere(&self, s: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.distance(&s.center) >= -s.radius)
+    }
+
+    /// False only if the box is certainly outside. Boxes near a corner of
+    /// the frustum may pass without touching it.
+    pub fn intersects_aabb(&self, b: &Aabb) -> bool {
+        if b.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal.
+            let mut p = b.min;
+            for i in 0..3 {
+                if plane.normal[i] >= 0.0 {
+                    p[i] = b.max[i];
+                }
+            }
+            plane.distance(&p) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_meshes() {
+        let cube = MeshData::cube(2.0);
+        let aabb = cube.aabb();
+        assert_eq!(aabb.min, Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(aabb.max, Point3::new(1.0, 1.0, 1.0));
+        let sphere = cube.bounding_sphere();
+        assert_eq!(sphere.center, Point3::origin());
+        assert!((sphere.radius - 3f32.sqrt()).abs() < 1e-5);
+        assert!(MeshData::new("empty").aabb().is_empty());
+    }
+
+    #[test]
+    fn transforms_bounds() {
+        let aabb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let m = Matrix4::new_translation(&Vector3::new(5.0, 0.0, 0.0))
+            * Matrix4::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_4)
+            * Matrix4::new_scaling(2.0);
+        let moved = aabb.transform(&m);
+        let r = 2.0 * 2f32.sqrt();
+        assert!((moved.max - Point3::new(5.0 + r, r, 2.0)).norm() < 1e-4);
+        assert!((moved.min - Point3::new(5.0 - r, -r, -2.0)).norm() < 1e-4);
+
+        let sphere = BoundingSphere::new(Point3::new(1.0, 0.0, 0.0), 1.0).transform(&m);
+        assert!((sphere.radius - 2.0).abs() < 1e-5);
+        assert!((sphere.center - Point3::new(5.0 + 2f32.sqrt(), 2f32.sqrt(), 0.0)).norm() < 1e-4);
+    }
+
+    #[test]
+    fn culls_against_frustum() {
+        let proj = Matrix4::new_perspective(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let view = Matrix4::look_at_rh(
+            &Point3::new(0.0, 0.0, 10.0),
+            &Point3::origin(),
+            &Vector3::y(),
+        );
+        let frustum = Frustum::from_matrix(&(proj * view));
+
+        assert!(frustum.contains_point(&Point3::origin()));
+        assert!(!frustum.contains_point(&Point3::new(0.0, 0.0, 20.0)));
+
+        let unit = |x: f32, z: f32| BoundingSphere::new(Point3::new(x, 0.0, z), 1.0);
+        assert!(frustum.intersects_sphere(&unit(0.0, 0.0)));
+        assert!(!frustum.intersects_sphere(&unit(0.0, 12.0)));
+        assert!(!frustum.intersects_sphere(&unit(30.0, 0.0)));
+        // Straddling the right plane.
+        assert!(frustum.intersects_sphere(&unit(10.5, 0.0)));
+
+        let boxed = |x: f32| {
+            Aabb::new(
+                Point3::new(x - 1.0, -1.0, -1.0),
+                Point3::new(x + 1.0, 1.0, 1.0),
+            )
+        };
+        assert!(frustum.intersects_aabb(&boxed(0.0)));
+        assert!(frustum.intersects_aabb(&boxed(10.5)));
+        assert!(!frustum.intersects_aabb(&boxed(30.0)));
+        assert!(!frustum.intersects_aabb(&Aabb::empty()));
+    }
+}
@@ -1,6 +1,7 @@
 extern crate gl;
 
 use crate::texture::*;
+use crate::texture::readback;
 use gl::types::*;
 
 static TARGETS: [GLuint; 10] = [
@@ -54,20 +55,16 @@ impl Framebuffer {
     }
 
     pub fn new(w: usize, h: usize, bindings: &[TextureFormat]) -> Self {
-        assert!(bindings.iter().filter(
-                |i| match i { TextureFormat::Depth | TextureFormat::DepthStencil => true, _ => false }).count() <= 1,
+        assert!(bindings.iter().filter(|i| i.is_depth()).count() <= 1,
                 "A Framebuffer should have no more than one depth/depthstencil buffer.");
 
         let mut fb = Self::new_empty();
 
-        for b in bindings {
-            let t = match b {
-                TextureFormat::Rgba => Texture::new_rgba(w, h),
-                TextureFormat::Depth => Texture::new_depth(w, h),
-                TextureFormat::DepthStencil => Texture::new_depth_stencil(w, h),
-                _ => panic!("unsupported texture format bound to framebuffer")
-            };
-            fb.add_target(t); 
+        for &b in bindings {
+            if b == TextureFormat::Invalid {
+                panic!("unsupported texture format bound to framebuffer");
+            }
+            fb.add_target(Texture::new_with_storage(w, h, b));
         }
 
         fb
@@ -90,12 +87,25 @@ impl Framebuffer {
         &self.color[i]
     }
 
+    /// Reads color target `i` back, top row first; see `Texture::read_pixels`.
+    pub fn read_pixels(&self, i: usize) -> image::DynamicImage {
+        let target = &self.color[i];
+        unsafe {
+            let mut previous: GLint = 0;
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0 + i as GLuint);
+            let image = readback::read_framebuffer(target.format(), target.width(), target.height());
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous as GLuint);
+            image
+        }
+    }
+
     pub fn add_target(&mut self, texture: Texture) {
-        // TODO: support depth / stencil
         unsafe {
             gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
             match texture.format() {
-                TextureFormat::Rgba => {
+                f if f.is_color() => {
                     gl::FramebufferTexture2D(
                         gl::FRAMEBUFFER,
                         gl::COLOR_ATTACHMENT0 + self.color.len() as GLuint,
@@ -119,6 +129,20 @@ impl Framebuffer {
                     }
                     self.depth_stencil = Some(DepthStencil::Depth(texture));
                 }
+
+                TextureFormat::DepthStencil => {
+                    gl::FramebufferTexture2D(
+                        gl::FRAMEBUFFER,
+                        gl::DEPTH_STENCIL_ATTACHMENT,
+                        gl::TEXTURE_2D,
+                        texture.id(),
+                        0,
+                    );
+                    if self.depth_stencil.is_some() {
+                        panic!("attempt to attach more than one depth/depth_stencil to a framebuffer.");
+                    }
+                    self.depth_stencil = Some(DepthStencil::DepthStencil(texture));
+                }
                 _ => {
                     panic!("unhandled texture format added to framebuffer");
                 }
@@ -0,0 +1,21 @@
+uniform mat4 vpMatrix;
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 7) in mat4 model;
+layout(location = 11) in vec4 instance_color;
+
+smooth out vec4 fnormal;
+smooth out vec4 fposition;
+smooth out vec2 fuv;
+smooth out vec4 fcolor;
+
+void main()
+{
+    fposition = model * vec4(position, 1.0f);
+    gl_Position = vpMatrix * fposition;
+    fnormal = model * vec4(normal, 0.0f);
+    fuv = vec2(uv.x, -uv.y); // uv.y is inversed
+    fcolor = instance_color;
+}
@@ -0,0 +1,33 @@
+#version 130
+#extension GL_ARB_uniform_buffer_object : enable
+#extension GL_ARB_explicit_attrib_location : require
+
+uniform mat4 mvpMatrix;
+uniform mat4 mMatrix;
+uniform vec2 morph_weights;
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 12) in vec3 morph_position0;
+layout(location = 13) in vec3 morph_normal0;
+layout(location = 14) in vec3 morph_position1;
+layout(location = 15) in vec3 morph_normal1;
+
+smooth out vec4 fnormal;
+smooth out vec4 fposition;
+smooth out vec2 fuv;
+
+void main()
+{
+    vec3 p = position
+           + morph_position0 * morph_weights.x
+           + morph_position1 * morph_weights.y;
+    vec3 n = normalize(normal
+           + morph_normal0 * morph_weights.x
+           + morph_normal1 * morph_weights.y);
+    gl_Position = mvpMatrix * vec4(p, 1.0f);
+    fposition = mMatrix * vec4(p, 1.0f);
+    fnormal = mMatrix * vec4(n, 0.0f);
+    fuv = vec2(uv.x, -uv.y); // uv.y is inversed
+}
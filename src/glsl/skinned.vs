@@ -0,0 +1,34 @@
+#version 130
+#extension GL_ARB_uniform_buffer_object : enable
+#extension GL_ARB_explicit_attrib_location : require
+
+const int MAX_BONES = 64;
+
+uniform mat4 mvpMatrix;
+uniform mat4 mMatrix;
+uniform mat4 bone_matrices[MAX_BONES];
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 4) in uvec2 boneid;
+layout(location = 5) in vec2 boneweight;
+
+smooth out vec4 fnormal;
+smooth out vec4 fposition;
+smooth out vec2 fuv;
+
+void main()
+{
+    // Vertices without weights stay where they were modelled.
+    mat4 skin = mat4(1.0f);
+    if (boneweight.x + boneweight.y > 0.0f) {
+        skin = bone_matrices[boneid.x] * boneweight.x
+             + bone_matrices[boneid.y] * boneweight.y;
+    }
+    vec4 skinned = skin * vec4(position, 1.0f);
+    gl_Position = mvpMatrix * skinned;
+    fposition = mMatrix * skinned;
+    fnormal = mMatrix * skin * vec4(normal, 0.0f);
+    fuv = vec2(uv.x, -uv.y); // uv.y is inversed
+}
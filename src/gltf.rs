@@ -0,0 +1,698 @@
+use crate::mesh::{Face, MeshData, MorphTarget, Vertex};
+use crate::texture::Texture;
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum GltfError {
+    ReadError(io::Error),
+    ParseError(::gltf::Error),
+    ImageError(image::ImageError),
+    BadDataUri,
+    MissingBuffer(usize),
+    MissingPositions { mesh: usize, primitive: usize },
+    BadIndex { mesh: usize, primitive: usize },
+    UnsupportedMode { mesh: usize, primitive: usize },
+    TooManyVertices { mesh: usize, primitive: usize },
+    BadAttribute { mesh: usize, primitive: usize },
+    BadMorphTarget { mesh: usize, primitive: usize },
+    TooManyJoints(usize),
+    BadInverseBindMatrices(usize),
+    TooManyMaterials,
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::ReadError(e) => write!(f, "error reading glTF: {}", e),
+            GltfError::ParseError(e) => write!(f, "error parsing glTF: {}", e),
+            GltfError::ImageError(e) => write!(f, "error decoding glTF image: {}", e),
+            GltfError::BadDataUri => write!(f, "malformed data uri in glTF"),
+            GltfError::MissingBuffer(i) => write!(f, "glTF buffer {} has no data", i),
+            GltfError::MissingPositions { mesh, primitive } => write!(
+                f,
+                "glTF mesh {} primitive {} has no positions",
+                mesh, primitive
+            ),
+            GltfError::BadIndex { mesh, primitive } => write!(
+                f,
+                "glTF mesh {} primitive {} has an out of range index",
+                mesh, primitive
+            ),
+            GltfError::UnsupportedMode { mesh, primitive } => write!(
+                f,
+                "glTF mesh {} primitive {} is not a triangle list",
+                mesh, primitive
+            ),
+            GltfError::TooManyVertices { mesh, primitive } => write!(
+                f,
+                "glTF mesh {} primitive {} has too many vertices",
+                mesh, primitive
+            ),
+            GltfError::BadAttribute { mesh, primitive } => write!(
+                f,
+                "glTF mesh {} primitive {} has attributes of different lengths",
+                mesh, primitive
+            ),
+            GltfError::BadMorphTarget { mesh, primitive } => write!(
+                f,
+                "glTF mesh {} primitive {} has a morph target of the wrong length",
+                mesh, primitive
+            ),
+            GltfError::TooManyJoints(skin) => {
+                write!(f, "glTF skin {} has more than 256 joints", skin)
+            }
+            GltfError::BadInverseBindMatrices(skin) => write!(
+                f,
+                "glTF skin {} has a different number of inverse bind matrices and joints",
+                skin
+            ),
+            GltfError::TooManyMaterials => write!(f, "glTF has more than 256 materials"),
+        }
+    }
+}
+
+impl error::Error for GltfError {}
+
+/// A glTF mesh primitive, ready for `Mesh::from_data`.
+///
+/// Vertices are packed with `Vertex::new`, which clamps uvs to [0, 1], so
+/// textures tiled with uvs outside that range do not survive the import.
+pub struct GltfPrimitive {
+    pub data: MeshData,
+    pub material: Option<usize>,
+}
+
+pub struct GltfMesh {
+    pub name: String,
+    pub primitives: Vec<GltfPrimitive>,
+    /// Default morph target weights, unless animated.
+    pub weights: Vec<f32>,
+}
+
+/// A node's local transform is stored decomposed; `rotation` is a unit
+/// quaternion in x, y, z, w order.
+pub struct GltfNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    pub mesh: Option<usize>,
+    pub skin: Option<usize>,
+}
+
+/// `joints` are node indices; vertex `boneid`s index into this list.
+pub struct GltfSkin {
+    pub name: String,
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<[[f32; 4]; 4]>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GltfInterpolation {
+    Linear,
+    Step,
+    CubicSpline,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GltfChannelValues {
+    Translations(Vec<[f32; 3]>),
+    Rotations(Vec<[f32; 4]>),
+    Scales(Vec<[f32; 3]>),
+    MorphWeights(Vec<f32>),
+}
+
+pub struct GltfChannel {
+    pub node: usize,
+    pub interpolation: GltfInterpolation,
+    pub times: Vec<f32>,
+    pub values: GltfChannelValues,
+}
+
+pub struct GltfAnimation {
+    pub name: String,
+    pub channels: Vec<GltfChannel>,
+}
+
+pub struct GltfMaterial {
+    pub name: String,
+    pub base_color: [f32; 4],
+    /// Index into `GltfScene::images`.
+    pub base_color_image: Option<usize>,
+}
+
+/// Everything rockwork can use from a .gltf or .glb file, held on the CPU.
+pub struct GltfScene {
+    pub meshes: Vec<GltfMesh>,
+    pub nodes: Vec<GltfNode>,
+    /// Root nodes of the default scene (or the first scene).
+    pub roots: Vec<usize>,
+    pub skins: Vec<GltfSkin>,
+    pub animations: Vec<GltfAnimation>,
+    pub materials: Vec<GltfMaterial>,
+    pub images: Vec<image::DynamicImage>,
+}
+
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, GltfError> {
+    let comma = uri.find(',').ok_or(GltfError::BadDataUri)?;
+    if !uri[..comma].ends_with(";base64") {
+        return Err(GltfError::BadDataUri);
+    }
+    base64::decode(&uri[comma + 1..]).map_err(|_| GltfError::BadDataUri)
+}
+
+fn load_uri(uri: &str, base: Option<&Path>) -> Result<Vec<u8>, GltfError> {
+    if uri.starts_with("data:") {
+        return decode_data_uri(uri);
+    }
+    let path = match base {
+        Some(dir) => dir.join(uri),
+        None => Path::new(uri).to_path_buf(),
+    };
+    fs::read(path).map_err(GltfError::ReadError)
+}
+
+/// Keeps the two strongest of glTF's four joint influences, renormalized.
+fn strongest_joints(joints: [u16; 4], weights: [f32; 4]) -> ([u8; 2], [f32; 2]) {
+    let mut order = [0, 1, 2, 3];
+    order.sort_by(|&a, &b| {
+        weights[b]
+            .partial_cmp(&weights[a])
+            .unwrap_or(Ordering::Equal)
+    });
+    let (a, b) = (order[0], order[1]);
+    let total = weights[a] + weights[b];
+    if total <= 0.0 {
+        return ([0, 0], [0.0, 0.0]);
+    }
+    (
+        [joints[a] as u8, joints[b] as u8],
+        [weights[a] / total, weights[b] / total],
+    )
+}
+
+impl GltfScene {
+    /// Loads a .gltf or .glb file; external buffers and images are resolved
+    /// relative to it.
+    pub fn open(path: &Path) -> Result<Self, GltfError> {
+        let bytes = fs::read(path).map_err(GltfError::ReadError)?;
+        Self::from_slice(&bytes, path.parent())
+    }
+
+    /// Loads .gltf or .glb data. Relative uris are resolved against `base`,
+    /// or the working directory if there is none.
+    pub fn from_slice(bytes: &[u8], base: Option<&Path>) -> Result<Self, GltfError> {
+        let ::gltf::Gltf { document, mut blob } =
+            ::gltf::Gltf::from_slice(bytes).map_err(GltfError::ParseError)?;
+
+        let mut buffers: Vec<Vec<u8>> = Vec::new();
+        for buffer in document.buffers() {
+            let data = match buffer.source() {
+                ::gltf::buffer::Source::Bin => blob
+                    .take()
+                    .ok_or_else(|| GltfError::MissingBuffer(buffer.index()))?,
+                ::gltf::buffer::Source::Uri(uri) => load_uri(uri, base)?,
+            };
+            if data.len() < buffer.length() {
+                return Err(GltfError::MissingBuffer(buffer.index()));
+            }
+            buffers.push(data);
+        }
+        let get_buffer = |b: ::gltf::Buffer| buffers.get(b.index()).map(|d| d.as_slice());
+
+        let mut images = Vec::new();
+        for img in document.images() {
+            let decoded = match img.source() {
+                ::gltf::image::Source::View { view, .. } => {
+                    let missing = || GltfError::MissingBuffer(view.buffer().index());
+                    let data = get_buffer(view.buffer()).ok_or_else(missing)?;
+                    let end = view
+                        .offset()
+                        .checked_add(view.length())
+                        .ok_or_else(missing)?;
+                    image::load_from_memory(data.get(view.offset()..end).ok_or_else(missing)?)
+                }
+                ::gltf::image::Source::Uri { uri, .. } => {
+                    image::load_from_memory(&load_uri(uri, base)?)
+                }
+            };
+            images.push(decoded.map_err(GltfError::ImageError)?);
+        }
+
+        if document.materials().len() > u8::MAX as usize + 1 {
+            return Err(GltfError::TooManyMaterials);
+        }
+        let materials = document
+            .materials()
+            .map(|m| {
+                let pbr = m.pbr_metallic_roughness();
+                GltfMaterial {
+                    name: m.name().unwrap_or("").to_string(),
+                    base_color: pbr.base_color_factor(),
+                    base_color_image: pbr
+                        .base_color_texture()
+                        .map(|info| info.texture().source().index()),
+                }
+            })
+            .collect();
+
+        let mut meshes = Vec::new();
+        for mesh in document.meshes() {
+            let mut primitives = Vec::new();
+            for prim in mesh.primitives() {
+                let (m, p) = (mesh.index(), prim.index());
+                if prim.mode() != ::gltf::mesh::Mode::Triangles {
+                    return Err(GltfError::UnsupportedMode {
+                        mesh: m,
+                        primitive: p,
+                    });
+                }
+
+                let reader = prim.reader(get_buffer);
+                let positions: Vec<[f32; 3]> = reader
+                    .read_positions()
+                    .ok_or(GltfError::MissingPositions {
+                        mesh: m,
+                        primitive: p,
+                    })?
+                    .collect();
+                let n = positions.len();
+                if n > u32::MAX as usize {
+                    return Err(GltfError::TooManyVertices {
+                        mesh: m,
+                        primitive: p,
+                    });
+                }
+
+                let normals: Vec<[f32; 3]> = reader
+                    .read_normals()
+                    .map_or_else(|| vec![[0.0; 3]; n], |r| r.collect());
+                let uvs: Vec<[f32; 2]> = reader
+                    .read_tex_coords(0)
+                    .map_or_else(|| vec![[0.0; 2]; n], |r| r.into_f32().collect());
+                let colors: Vec<[f32; 3]> = reader
+                    .read_colors(0)
+                    .map_or_else(|| vec![[1.0; 3]; n], |r| r.into_rgb_f32().collect());
+                let joints: Vec<[u16; 4]> = reader
+                    .read_joints(0)
+                    .map_or_else(|| vec![[0; 4]; n], |r| r.into_u16().collect());
+                let weights: Vec<[f32; 4]> = reader
+                    .read_weights(0)
+                    .map_or_else(|| vec![[0.0; 4]; n], |r| r.into_f32().collect());
+                let lengths = [
+                    normals.len(),
+                    uvs.len(),
+                    colors.len(),
+                    joints.len(),
+                    weights.len(),
+                ];
+                if lengths.iter().any(|&len| len != n) {
+                    return Err(GltfError::BadAttribute {
+                        mesh: m,
+                        primitive: p,
+                    });
+                }
+                let material = prim.material().index();
+                let material_id = match material {
+                    Some(i) if i > u8::MAX as usize => return Err(GltfError::TooManyMaterials),
+                    Some(i) => i as u8,
+                    None => 0,
+                };
+
+                let mut data = MeshData::new(mesh.name().unwrap_or(""));
+                for i in 0..n {
+                    let (ids, w) = strongest_joints(joints[i], weights[i]);
+                    // glTF puts the uv origin at the top left; rockwork at the
+                    // bottom left. Tiling uvs get clamped to [0, 1] here.
+                    let uv = [uvs[i][0], 1.0 - uvs[i][1]];
+                    data.vertices.push(
+                        Vertex::new(positions[i], normals[i], uv, colors[i])
+                            .with_bones(ids, w)
+                            .with_material(material_id),
+                    );
+                }
+
+                // Target names live in extras, which are not loaded, so
+                // targets are only known by index.
+                for (positions, normals, _) in reader.read_morph_targets() {
+                    let mut target = MorphTarget::new("", n);
+                    if let Some(d) = positions {
+                        target.positions = d.collect();
+                    }
+                    if let Some(d) = normals {
+                        target.normals = d.collect();
+                    }
+                    if !target.fits(n) {
+                        return Err(GltfError::BadMorphTarget {
+                            mesh: m,
+                            primitive: p,
+                        });
+                    }
+                    data.morph_targets.push(target);
+                }
+
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(r) => r.into_u32().collect(),
+                    None => (0..n as u32).collect(),
+                };
+                // glTF fronts are counter-clockwise; rockwork's are clockwise.
+                for tri in indices.chunks_exact(3) {
+                    if tri.iter().any(|&i| i as usize >= n) {
+                        return Err(GltfError::BadIndex {
+                            mesh: m,
+                            primitive: p,
+                        });
+                    }
+                    data.faces.push(Face::new([tri[0], tri[2], tri[1]]));
+                }
+                primitives.push(GltfPrimitive { data, material });
+            }
+            meshes.push(GltfMesh {
+                name: mesh.name().unwrap_or("").to_string(),
+                primitives,
+                weights: mesh.weights().map_or_else(Vec::new, |w| w.to_vec()),
+            });
+        }
+
+        let mut nodes: Vec<GltfNode> = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                GltfNode {
+                    name: node.name().unwrap_or("").to_string(),
+                    parent: None,
+                    children: node.children().map(|c| c.index()).collect(),
+                    translation,
+                    rotation,
+                    scale,
+                    mesh: node.mesh().map(|m| m.index()),
+                    skin: node.skin().map(|s| s.index()),
+                }
+            })
+            .collect();
+        for i in 0..nodes.len() {
+            for c in nodes[i].children.clone() {
+                nodes[c].parent = Some(i);
+            }
+        }
+
+        let roots = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .map_or_else(Vec::new, |s| s.nodes().map(|n| n.index()).collect());
+
+        let mut skins = Vec::new();
+        for skin in document.skins() {
+            let joints: Vec<usize> = skin.joints().map(|j| j.index()).collect();
+            if joints.len() > u8::MAX as usize + 1 {
+                return Err(GltfError::TooManyJoints(skin.index()));
+            }
+            let inverse_bind_matrices = match skin.reader(get_buffer).read_inverse_bind_matrices() {
+                Some(r) => r.collect(),
+                None => {
+                    let identity = [
+                        [1.0, 0.0, 0.0, 0.0],
+                        [0.0, 1.0, 0.0, 0.0],
+                        [0.0, 0.0, 1.0, 0.0],
+                        [0.0, 0.0, 0.0, 1.0],
+                    ];
+                    vec![identity; joints.len()]
+                }
+            };
+            if inverse_bind_matrices.len() != joints.len() {
+                return Err(GltfError::BadInverseBindMatrices(skin.index()));
+            }
+            skins.push(GltfSkin {
+                name: skin.name().unwrap_or("").to_string(),
+                joints,
+                inverse_bind_matrices,
+            });
+        }
+
+        let mut animations = Vec::new();
+        for anim in document.animations() {
+            let mut channels = Vec::new();
+            for channel in anim.channels() {
+                let reader = channel.reader(get_buffer);
+                let times = match reader.read_inputs() {
+                    Some(r) => r.collect(),
+                    None => continue,
+                };
+                let values = match reader.read_outputs() {
+                    Some(::gltf::animation::util::ReadOutputs::Translations(r)) => {
+                        GltfChannelValues::Translations(r.collect())
+                    }
+                    Some(::gltf::animation::util::ReadOutputs::Rotations(r)) => {
+                        GltfChannelValues::Rotations(r.into_f32().collect())
+                    }
+                    Some(::gltf::animation::util::ReadOutputs::Scales(r)) => {
+                        GltfChannelValues::Scales(r.collect())
+                    }
+                    Some(::gltf::animation::util::ReadOutputs::MorphTargetWeights(r)) => {
+                        GltfChannelValues::MorphWeights(r.into_f32().collect())
+                    }
+                    None => continue,
+                };
+                let interpolation = match channel.sampler().interpolation() {
+                    ::gltf::animation::Interpolation::Linear => GltfInterpolation::Linear,
+                    ::gltf::animation::Interpolation::Step => GltfInterpolation::Step,
+                    ::gltf::animation::Interpolation::CubicSpline => GltfInterpolation::CubicSpline,
+                };
+                channels.push(GltfChannel {
+                    node: channel.target().node().index(),
+                    interpolation,
+                    times,
+                    values,
+                });
+            }
+            animations.push(GltfAnimation {
+                name: anim.name().unwrap_or("").to_string(),
+                channels,
+            });
+        }
+
+        Ok(GltfScene {
+            meshes,
+            nodes,
+            roots,
+            skins,
+            animations,
+            materials,
+            images,
+        })
+    }
+
+    /// Uploads every image; indices match `images` and
+    /// `GltfMaterial::base_color_image`.
+    pub fn load_textures(&self) -> Vec<Texture> {
+        self.images
+            .iter()
+            .map(|img| Texture::new_rgba_from_image(&mut img.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::morph::MorphWeightTrack;
+
+    fn push_f32s(buf: &mut Vec<u8>, values: &[f32]) {
+        for v in values {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    /// A skinned triangle with one morph target under a parent node, with an
+    /// animation moving a bone and the target's weight.
+    fn triangle_gltf() -> String {
+        let mut bin = Vec::new();
+        // 0: positions, 36 bytes
+        push_f32s(&mut bin, &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        // 36: uvs, 24 bytes
+        push_f32s(&mut bin, &[0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+        // 60: joints, 12 bytes
+        bin.extend_from_slice(&[0, 1, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0]);
+        // 72: weights, 48 bytes
+        push_f32s(
+            &mut bin,
+            &[0.2, 0.6, 0.2, 0.0, 1.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.0, 0.0],
+        );
+        // 120: indices, 6 bytes + 2 padding
+        bin.extend_from_slice(&[0, 0, 1, 0, 2, 0, 0, 0]);
+        // 128: keyframe times, 8 bytes
+        push_f32s(&mut bin, &[0.0, 1.0]);
+        // 136: translations, 24 bytes
+        push_f32s(&mut bin, &[0.0, 0.0, 0.0, 0.0, 2.0, 0.0]);
+
+        format!(
+            r#"{{
+  "asset": {{"version": "2.0"}},
+  "scene": 0,
+  "scenes": [{{"nodes": [0]}}],
+  "nodes": [
+    {{"name": "root", "children": [1, 2], "translation": [1, 2, 3]}},
+    {{"name": "tri", "mesh": 0, "skin": 0}},
+    {{"name": "bone"}}
+  ],
+  "meshes": [{{"name": "tri", "primitives": [{{
+    "attributes": {{"POSITION": 0, "TEXCOORD_0": 1, "JOINTS_0": 2, "WEIGHTS_0": 3}},
+    "indices": 4,
+    "material": 0,
+    "targets": [{{"POSITION": 0}}]
+  }}], "weights": [0.5]}}],
+  "materials": [{{"name": "red", "pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1]}}}}],
+  "skins": [{{"joints": [0, 2]}}],
+  "animations": [{{"name": "bounce", "channels": [
+      {{"sampler": 0, "target": {{"node": 2, "path": "translation"}}}},
+      {{"sampler": 1, "target": {{"node": 1, "path": "weights"}}}}
+    ],
+    "samplers": [
+      {{"input": 5, "output": 6, "interpolation": "STEP"}},
+      {{"input": 5, "output": 5}}
+    ]}}],
+  "buffers": [{{"byteLength": {len}, "uri": "data:application/octet-stream;base64,{data}"}}],
+  "bufferViews": [
+    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+    {{"buffer": 0, "byteOffset": 36, "byteLength": 24}},
+    {{"buffer": 0, "byteOffset": 60, "byteLength": 12}},
+    {{"buffer": 0, "byteOffset": 72, "byteLength": 48}},
+    {{"buffer": 0, "byteOffset": 120, "byteLength": 6}},
+    {{"buffer": 0, "byteOffset": 128, "byteLength": 8}},
+    {{"buffer": 0, "byteOffset": 136, "byteLength": 24}}
+  ],
+  "accessors": [
+    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+    {{"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2"}},
+    {{"bufferView": 2, "componentType": 5121, "count": 3, "type": "VEC4"}},
+    {{"bufferView": 3, "componentType": 5126, "count": 3, "type": "VEC4"}},
+    {{"bufferView": 4, "componentType": 5123, "count": 3, "type": "SCALAR"}},
+    {{"bufferView": 5, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0], "max": [1]}},
+    {{"bufferView": 6, "componentType": 5126, "count": 2, "type": "VEC3"}}
+  ]
+}}"#,
+            len = bin.len(),
+            data = base64::encode(&bin)
+        )
+    }
+
+    #[test]
+    fn loads_skinned_triangle() {
+        let scene = GltfScene::from_slice(triangle_gltf().as_bytes(), None).unwrap();
+
+        assert_eq!(scene.meshes.len(), 1);
+        let prim = &scene.meshes[0].primitives[0];
+        assert_eq!(prim.material, Some(0));
+        // Winding is flipped to clockwise.
+        assert_eq!(prim.data.faces, vec![Face::new([0, 2, 1])]);
+
+        let v = &prim.data.vertices[0];
+        assert_eq!(v.position(), [0.0, 0.0, 0.0]);
+        assert_eq!(v.uv(), [0.0, 1.0]);
+        // Joint 1 at 0.6 beats the two 0.2s; the pair is renormalized.
+        assert_eq!(v.bone_ids()[0], 1);
+        assert!((v.bone_weights()[0] - 0.75).abs() < 0.01);
+        assert_eq!(prim.data.vertices[1].bone_weights(), [1.0, 0.0]);
+
+        assert_eq!(scene.roots, vec![0]);
+        assert_eq!(scene.nodes[0].translation, [1.0, 2.0, 3.0]);
+        assert_eq!(scene.nodes[2].parent, Some(0));
+        assert_eq!(scene.nodes[1].skin, Some(0));
+        assert_eq!(scene.skins[0].joints, vec![0, 2]);
+        assert_eq!(scene.skins[0].inverse_bind_matrices.len(), 2);
+        assert_eq!(scene.materials[0].base_color, [1.0, 0.0, 0.0, 1.0]);
+
+        let channel = &scene.animations[0].channels[0];
+        assert_eq!(scene.animations[0].name, "bounce");
+        assert_eq!(channel.node, 2);
+        assert_eq!(channel.interpolation, GltfInterpolation::Step);
+        assert_eq!(channel.times, vec![0.0, 1.0]);
+        assert_eq!(
+            channel.values,
+            GltfChannelValues::Translations(vec![[0.0, 0.0, 0.0], [0.0, 2.0, 0.0]])
+        );
+
+        // The target reuses the positions as deltas.
+        assert_eq!(scene.meshes[0].weights, vec![0.5]);
+        let target = &prim.data.morph_targets[0];
+        assert_eq!(target.positions[1], [1.0, 0.0, 0.0]);
+        assert!(target.normals.is_empty());
+        let track = MorphWeightTrack::from_gltf(&scene, 0, 1).unwrap();
+        assert_eq!(track.ntargets, 1);
+        let mut w = [0.0];
+        track.sample(0.25, false, &mut w);
+        assert_eq!(w, [0.25]);
+        assert!(MorphWeightTrack::from_gltf(&scene, 0, 2).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        match GltfScene::from_slice(b"{ not json", None) {
+            Err(GltfError::ParseError(_)) => {}
+            _ => panic!("expected a parse error"),
+        }
+
+        let broken = triangle_gltf().replace(";base64,", ";base64,!!");
+        match GltfScene::from_slice(broken.as_bytes(), None) {
+            Err(GltfError::BadDataUri) => {}
+            _ => panic!("expected a data uri error"),
+        }
+
+        // An image view running past the end of its buffer.
+        let overrun = triangle_gltf()
+            .replace(
+                r#""buffers":"#,
+                r#""images": [{"bufferView": 7, "mimeType": "image/png"}], "buffers":"#,
+            )
+            .replace(
+                r#""byteOffset": 136, "byteLength": 24}"#,
+                r#""byteOffset": 136, "byteLength": 24},
+    {"buffer": 0, "byteOffset": 150, "byteLength": 100}"#,
+            );
+        match GltfScene::from_slice(overrun.as_bytes(), None) {
+            Err(GltfError::MissingBuffer(0)) => {}
+            _ => panic!("expected a missing buffer error"),
+        }
+
+        // One inverse bind matrix for a skin with two joints.
+        let short = triangle_gltf().replace(
+            r#""skins": [{"joints": [0, 2]}]"#,
+            r#""skins": [{"joints": [0, 2], "inverseBindMatrices": 7}]"#,
+        );
+        let short = short.replace(
+            r#"{"bufferView": 6, "componentType": 5126, "count": 2, "type": "VEC3"}"#,
+            r#"{"bufferView": 6, "componentType": 5126, "count": 2, "type": "VEC3"},
+    {"bufferView": 7, "componentType": 5126, "count": 1, "type": "MAT4"}"#,
+        );
+        let short = short.replace(
+            r#""byteOffset": 136, "byteLength": 24}"#,
+            r#""byteOffset": 136, "byteLength": 24},
+    {"buffer": 0, "byteOffset": 0, "byteLength": 64}"#,
+        );
+        match GltfScene::from_slice(short.as_bytes(), None) {
+            Err(GltfError::BadInverseBindMatrices(0)) => {}
+            _ => panic!("expected an inverse bind matrix error"),
+        }
+
+        // Normals for two of the three vertices.
+        let short = triangle_gltf()
+            .replace(r#""TEXCOORD_0": 1,"#, r#""TEXCOORD_0": 1, "NORMAL": 7,"#)
+            .replace(
+                r#"{"bufferView": 6, "componentType": 5126, "count": 2, "type": "VEC3"}"#,
+                r#"{"bufferView": 6, "componentType": 5126, "count": 2, "type": "VEC3"},
+    {"bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3"}"#,
+            );
+        match GltfScene::from_slice(short.as_bytes(), None) {
+            Err(GltfError::BadAttribute {
+                mesh: 0,
+                primitive: 0,
+            }) => {}
+            _ => panic!("expected an attribute length error"),
+        }
+    }
+}
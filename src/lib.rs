@@ -1,9 +1,14 @@
+pub mod bounds;
 pub mod context;
 pub mod draw_device;
 pub mod framebuffer;
+pub mod gltf;
 pub mod mesh;
 pub mod program;
+pub mod proto;
+pub mod ray;
 pub mod shader;
+pub mod skeleton;
 pub mod texture;
 pub mod window;
 
@@ -1,7 +1,26 @@
+use crate::bounds::{Aabb, BoundingSphere};
 use gl::types::*;
-use std::ffi::c_void;
-use std::io::Cursor;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::{error, fmt, io};
+
+pub mod adjacency;
+pub mod instancing;
+pub mod layout;
+pub mod lod;
+pub mod material;
+pub mod morph;
+pub mod obj;
+pub mod optimize;
+pub mod primitives;
+pub mod protobuf;
+pub mod simplify;
+pub mod tangents;
+
+pub use self::instancing::{Instance, InstancedDraw};
+pub use self::layout::{AttribType, VertexAttrib, VertexLayout};
+pub use self::lod::{LodLevel, LodMesh};
+pub use self::material::{Material, SubMesh};
+pub use self::morph::{MorphTarget, MorphVertex};
 
 #[macro_export]
 macro_rules! include_mdl {
@@ -10,7 +29,7 @@ macro_rules! include_mdl {
     };
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq)]
 #[repr(C)]
 pub struct Vertex {
     position: [f32; 3],
@@ -23,12 +42,19 @@ pub struct Vertex {
     incident_edge_id: u16,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq)]
 #[repr(C)]
 pub struct Face {
-    vertex_ids: [u16; 3],
+    vertex_ids: [u32; 3],
 }
 
+/// A winged edge, as stored in the .mdl edge section.
+///
+/// `face_ids` are the faces on either side of the edge, `NO_ID` on a
+/// boundary. For the face in `face_ids[i]`, `first_edges[i]` is the other edge
+/// of that face touching `vertex_ids[0]` and `second_edges[i]` is the one
+/// touching `vertex_ids[1]`.
+#[derive(Default, Debug, Clone, PartialEq)]
 #[repr(C)]
 pub struct Edge {
     vertex_ids: [u16; 2],
@@ -46,7 +72,1025 @@ pub struct MdlHeader {
     nfaces: u32,
     nedges: u32,
     nbones: u8,
-    name: [u8; 15],
+    name: [u8; MDL_NAME_LEN],
+}
+
+/// Newest .mdl layout understood by `MeshData::from_mdl`. Version 5 adds
+/// morph targets after the edges, version 6 a material table and
+/// sub-meshes after those.
+pub const MDL_VERSION: u8 = 6;
+
+/// Oldest .mdl layout still read.
+pub const MDL_MIN_VERSION: u8 = 4;
+
+/// Faces index vertices with a u16, so a .mdl can never address more than this.
+pub const MDL_MAX_VERTICES: u32 = 1 << 16;
+pub const MDL_MAX_FACES: u32 = 1 << 22;
+pub const MDL_MAX_EDGES: u32 = 1 << 22;
+pub const MDL_MAX_MORPH_TARGETS: u32 = 256;
+/// `Vertex::material` is a u8.
+pub const MDL_MAX_MATERIALS: u32 = 256;
+pub const MDL_MAX_SUBMESHES: u32 = 1 << 16;
+
+/// Material names and textures are stored with a u8 length.
+pub const MDL_MAX_STRING_LEN: usize = 255;
+
+pub const MDL_NAME_LEN: usize = 15;
+
+/// Upper bound on how much is reserved up front; counts in the header are
+/// untrusted, so anything beyond this grows as data actually arrives.
+const MDL_MAX_PREALLOC: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MdlSection {
+    Header,
+    Vertices,
+    Faces,
+    Edges,
+    MorphTargets,
+    Materials,
+    SubMeshes,
+}
+
+#[derive(Debug)]
+pub enum MdlError {
+    ReadError(io::Error),
+    WriteError(io::Error),
+    BadMagic([u8; 3]),
+    UnsupportedVersion(u8),
+    Truncated(MdlSection),
+    TooLarge {
+        section: MdlSection,
+        count: u32,
+        limit: u32,
+    },
+    VertexOutOfRange {
+        face: usize,
+        vertex: u32,
+        nverts: usize,
+    },
+    NameTooLong(String),
+    StringTooLong(String),
+    BadMorphTarget(usize),
+    BadSubMesh(usize),
+}
+
+impl fmt::Display for MdlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdlError::ReadError(e) => write!(f, "error reading .mdl: {}", e),
+            MdlError::WriteError(e) => write!(f, "error writing .mdl: {}", e),
+            MdlError::BadMagic(m) => write!(
+                f,
+                "bad header in .mdl file: {:?}",
+                String::from_utf8_lossy(m)
+            ),
+            MdlError::UnsupportedVersion(v) => write!(f, "unsupported .mdl version {}", v),
+            MdlError::Truncated(section) => write!(f, ".mdl file truncated in {:?}", section),
+            MdlError::TooLarge {
+                section,
+                count,
+                limit,
+            } => write!(
+                f,
+                ".mdl {:?} count {} exceeds limit of {}",
+                section, count, limit
+            ),
+            MdlError::VertexOutOfRange {
+                face,
+                vertex,
+                nverts,
+            } => write!(
+                f,
+                ".mdl face {} references vertex {} but there are only {} vertices",
+                face, vertex, nverts
+            ),
+            MdlError::NameTooLong(name) => write!(
+                f,
+                ".mdl name {:?} does not fit in {} bytes",
+                name, MDL_NAME_LEN
+            ),
+            MdlError::StringTooLong(s) => write!(
+                f,
+                ".mdl string {:?} does not fit in {} bytes",
+                s, MDL_MAX_STRING_LEN
+            ),
+            MdlError::BadMorphTarget(t) => write!(
+                f,
+                ".mdl morph target {} does not have one delta per vertex",
+                t
+            ),
+            MdlError::BadSubMesh(s) => write!(f, ".mdl sub-mesh {} is outside the faces", s),
+        }
+    }
+}
+
+impl error::Error for MdlError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MdlError::ReadError(e) | MdlError::WriteError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Little-endian reader that maps a short read onto the section being parsed.
+struct MdlReader<'a> {
+    f: &'a mut dyn Read,
+    section: MdlSection,
+}
+
+impl<'a> MdlReader<'a> {
+    fn new(f: &'a mut dyn Read) -> Self {
+        MdlReader {
+            f,
+            section: MdlSection::Header,
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), MdlError> {
+        self.f.read_exact(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => MdlError::Truncated(self.section),
+            _ => MdlError::ReadError(e),
+        })
+    }
+
+    fn read_u8(&mut self) -> Result<u8, MdlError> {
+        let mut b = [0u8; 1];
+        self.read_bytes(&mut b)?;
+        Ok(b[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, MdlError> {
+        let mut b = [0u8; 2];
+        self.read_bytes(&mut b)?;
+        Ok(u16::from_le_bytes(b))
+    }
+
+    fn read_i16(&mut self) -> Result<i16, MdlError> {
+        let mut b = [0u8; 2];
+        self.read_bytes(&mut b)?;
+        Ok(i16::from_le_bytes(b))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, MdlError> {
+        let mut b = [0u8; 4];
+        self.read_bytes(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, MdlError> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    fn read_count(&mut self, section: MdlSection, limit: u32) -> Result<u32, MdlError> {
+        let count = self.read_u32()?;
+        if count > limit {
+            return Err(MdlError::TooLarge {
+                section,
+                count,
+                limit,
+            });
+        }
+        Ok(count)
+    }
+}
+
+/// Little-endian counterpart of `MdlReader`.
+struct MdlWriter<'a, W: Write> {
+    w: &'a mut W,
+}
+
+impl<'a, W: Write> MdlWriter<'a, W> {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), MdlError> {
+        self.w.write_all(buf).map_err(MdlError::WriteError)
+    }
+
+    fn write_u8(&mut self, v: u8) -> Result<(), MdlError> {
+        self.write_bytes(&[v])
+    }
+
+    fn write_u16(&mut self, v: u16) -> Result<(), MdlError> {
+        self.write_bytes(&v.to_le_bytes())
+    }
+
+    fn write_i16(&mut self, v: i16) -> Result<(), MdlError> {
+        self.write_bytes(&v.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, v: u32) -> Result<(), MdlError> {
+        self.write_bytes(&v.to_le_bytes())
+    }
+
+    fn write_f32(&mut self, v: f32) -> Result<(), MdlError> {
+        self.write_u32(v.to_bits())
+    }
+}
+
+fn check_mdl_count(section: MdlSection, count: usize, limit: u32) -> Result<u32, MdlError> {
+    if count > limit as usize {
+        return Err(MdlError::TooLarge {
+            section,
+            count: count.min(u32::MAX as usize) as u32,
+            limit,
+        });
+    }
+    Ok(count as u32)
+}
+
+impl MdlHeader {
+    fn write<W: Write>(&self, w: &mut MdlWriter<W>) -> Result<(), MdlError> {
+        w.write_bytes(&self.magic)?;
+        w.write_u8(self.version)?;
+        w.write_u32(self.nverts)?;
+        w.write_u32(self.nfaces)?;
+        w.write_u32(self.nedges)?;
+        w.write_u8(self.nbones)?;
+        w.write_bytes(&self.name)
+    }
+
+    fn read(r: &mut MdlReader) -> Result<Self, MdlError> {
+        r.section = MdlSection::Header;
+        let mut header: MdlHeader = Default::default();
+        r.read_bytes(&mut header.magic)?;
+        if &header.magic != b"MDL" {
+            return Err(MdlError::BadMagic(header.magic));
+        }
+
+        header.version = r.read_u8()?;
+        if header.version < MDL_MIN_VERSION || header.version > MDL_VERSION {
+            return Err(MdlError::UnsupportedVersion(header.version));
+        }
+
+        header.nverts = r.read_count(MdlSection::Vertices, MDL_MAX_VERTICES)?;
+        header.nfaces = r.read_count(MdlSection::Faces, MDL_MAX_FACES)?;
+        header.nedges = r.read_count(MdlSection::Edges, MDL_MAX_EDGES)?;
+        header.nbones = r.read_u8()?;
+        r.read_bytes(&mut header.name)?;
+        Ok(header)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn nverts(&self) -> u32 {
+        self.nverts
+    }
+
+    pub fn nfaces(&self) -> u32 {
+        self.nfaces
+    }
+
+    pub fn nedges(&self) -> u32 {
+        self.nedges
+    }
+
+    pub fn nbones(&self) -> u8 {
+        self.nbones
+    }
+
+    /// The name field, up to the first NUL.
+    pub fn name(&self) -> String {
+        unpack_mdl_name(&self.name)
+    }
+}
+
+fn unpack_mdl_name(name: &[u8; MDL_NAME_LEN]) -> String {
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+fn pack_mdl_name(name: &str) -> Result<[u8; MDL_NAME_LEN], MdlError> {
+    let bytes = name.as_bytes();
+    if bytes.len() > MDL_NAME_LEN {
+        return Err(MdlError::NameTooLong(name.to_string()));
+    }
+    let mut packed = [0; MDL_NAME_LEN];
+    packed[..bytes.len()].copy_from_slice(bytes);
+    Ok(packed)
+}
+
+fn read_mdl_vec3s(r: &mut MdlReader, n: usize) -> Result<Vec<[f32; 3]>, MdlError> {
+    let mut out = Vec::with_capacity(n.min(MDL_MAX_PREALLOC));
+    for _ in 0..n {
+        out.push([r.read_f32()?, r.read_f32()?, r.read_f32()?]);
+    }
+    Ok(out)
+}
+
+impl MorphTarget {
+    fn write_mdl<W: Write>(&self, w: &mut MdlWriter<W>) -> Result<(), MdlError> {
+        w.write_bytes(&pack_mdl_name(&self.name)?)?;
+        w.write_u8(!self.normals.is_empty() as u8)?;
+        for d in self.positions.iter().chain(self.normals.iter()) {
+            for &c in d.iter() {
+                w.write_f32(c)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_mdl(r: &mut MdlReader, nverts: usize) -> Result<Self, MdlError> {
+        let mut name = [0; MDL_NAME_LEN];
+        r.read_bytes(&mut name)?;
+        let has_normals = r.read_u8()? != 0;
+        let positions = read_mdl_vec3s(r, nverts)?;
+        let normals = if has_normals {
+            read_mdl_vec3s(r, nverts)?
+        } else {
+            Vec::new()
+        };
+        Ok(MorphTarget {
+            name: unpack_mdl_name(&name),
+            positions,
+            normals,
+        })
+    }
+}
+
+fn check_mdl_string(s: &str) -> Result<(), MdlError> {
+    if s.len() > MDL_MAX_STRING_LEN {
+        return Err(MdlError::StringTooLong(s.to_string()));
+    }
+    Ok(())
+}
+
+/// Callers check the length with `check_mdl_string` first.
+fn write_mdl_string<W: Write>(w: &mut MdlWriter<W>, s: &str) -> Result<(), MdlError> {
+    w.write_u8(s.len() as u8)?;
+    w.write_bytes(s.as_bytes())
+}
+
+fn read_mdl_string(r: &mut MdlReader) -> Result<String, MdlError> {
+    let mut bytes = vec![0; r.read_u8()? as usize];
+    r.read_bytes(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+impl Material {
+    fn write_mdl<W: Write>(&self, w: &mut MdlWriter<W>) -> Result<(), MdlError> {
+        write_mdl_string(w, &self.name)?;
+        for &c in self.base_color.iter() {
+            w.write_f32(c)?;
+        }
+        w.write_u8(self.texture.is_some() as u8)?;
+        if let Some(texture) = &self.texture {
+            write_mdl_string(w, texture)?;
+        }
+        Ok(())
+    }
+
+    fn read_mdl(r: &mut MdlReader) -> Result<Self, MdlError> {
+        let mut m = Material::new(&read_mdl_string(r)?);
+        for c in m.base_color.iter_mut() {
+            *c = r.read_f32()?;
+        }
+        if r.read_u8()? != 0 {
+            m.texture = Some(read_mdl_string(r)?);
+        }
+        Ok(m)
+    }
+}
+
+impl SubMesh {
+    /// Callers check that the range fits the faces first.
+    fn write_mdl<W: Write>(&self, w: &mut MdlWriter<W>) -> Result<(), MdlError> {
+        w.write_u32(self.first_face as u32)?;
+        w.write_u32(self.nfaces as u32)?;
+        w.write_u8(self.material)
+    }
+
+    fn read_mdl(r: &mut MdlReader) -> Result<Self, MdlError> {
+        Ok(SubMesh {
+            first_face: r.read_u32()? as usize,
+            nfaces: r.read_u32()? as usize,
+            material: r.read_u8()?,
+        })
+    }
+
+    fn fits(&self, nfaces: usize) -> bool {
+        self.first_face <= nfaces && self.nfaces <= nfaces - self.first_face
+    }
+}
+
+fn pack_snorm16(f: f32) -> i16 {
+    (f.clamp(-1.0, 1.0) * 32767.0).round() as i16
+}
+
+fn unpack_snorm16(i: i16) -> f32 {
+    (i as f32 / 32767.0).max(-1.0)
+}
+
+fn pack_unorm16(f: f32) -> u16 {
+    (f.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+fn unpack_unorm16(i: u16) -> f32 {
+    i as f32 / 65535.0
+}
+
+fn pack_unorm8(f: f32) -> u8 {
+    (f.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn unpack_unorm8(i: u8) -> f32 {
+    i as f32 / 255.0
+}
+
+impl Vertex {
+    /// Packs float attributes into the on-disk/GPU layout.
+    ///
+    /// Normal components are clamped to [-1, 1]; uv and color components to
+    /// [0, 1].
+    pub fn new(position: [f32; 3], normal: [f32; 3], uv: [f32; 2], color: [f32; 3]) -> Self {
+        Vertex {
+            position,
+            normal: [
+                pack_snorm16(normal[0]),
+                pack_snorm16(normal[1]),
+                pack_snorm16(normal[2]),
+            ],
+            uv: [pack_unorm16(uv[0]), pack_unorm16(uv[1])],
+            color: [
+                pack_unorm8(color[0]),
+                pack_unorm8(color[1]),
+                pack_unorm8(color[2]),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Bone weights are clamped to [0, 1].
+    pub fn with_bones(mut self, ids: [u8; 2], weights: [f32; 2]) -> Self {
+        self.boneid = ids;
+        self.boneweight = [pack_unorm8(weights[0]), pack_unorm8(weights[1])];
+        self
+    }
+
+    pub fn with_material(mut self, material: u8) -> Self {
+        self.material = material;
+        self
+    }
+
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    pub fn normal(&self) -> [f32; 3] {
+        [
+            unpack_snorm16(self.normal[0]),
+            unpack_snorm16(self.normal[1]),
+            unpack_snorm16(self.normal[2]),
+        ]
+    }
+
+    pub fn uv(&self) -> [f32; 2] {
+        [unpack_unorm16(self.uv[0]), unpack_unorm16(self.uv[1])]
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        [
+            unpack_unorm8(self.color[0]),
+            unpack_unorm8(self.color[1]),
+            unpack_unorm8(self.color[2]),
+        ]
+    }
+
+    pub fn material(&self) -> u8 {
+        self.material
+    }
+
+    pub fn bone_ids(&self) -> [u8; 2] {
+        self.boneid
+    }
+
+    pub fn bone_weights(&self) -> [f32; 2] {
+        [
+            unpack_unorm8(self.boneweight[0]),
+            unpack_unorm8(self.boneweight[1]),
+        ]
+    }
+
+    pub fn incident_edge_id(&self) -> u16 {
+        self.incident_edge_id
+    }
+
+    pub fn set_position(&mut self, position: [f32; 3]) {
+        self.position = position;
+    }
+
+    pub fn set_normal(&mut self, normal: [f32; 3]) {
+        self.normal = [
+            pack_snorm16(normal[0]),
+            pack_snorm16(normal[1]),
+            pack_snorm16(normal[2]),
+        ];
+    }
+
+    pub fn set_uv(&mut self, uv: [f32; 2]) {
+        self.uv = [pack_unorm16(uv[0]), pack_unorm16(uv[1])];
+    }
+
+    pub fn set_color(&mut self, color: [f32; 3]) {
+        self.color = [
+            pack_unorm8(color[0]),
+            pack_unorm8(color[1]),
+            pack_unorm8(color[2]),
+        ];
+    }
+
+    fn write_mdl<W: Write>(&self, w: &mut MdlWriter<W>) -> Result<(), MdlError> {
+        for &p in self.position.iter() {
+            w.write_f32(p)?;
+        }
+        for &n in self.normal.iter() {
+            w.write_i16(n)?;
+        }
+        for &t in self.uv.iter() {
+            w.write_u16(t)?;
+        }
+        w.write_bytes(&self.color)?;
+        w.write_u8(self.material)?;
+        w.write_bytes(&self.boneid)?;
+        w.write_bytes(&self.boneweight)?;
+        w.write_u16(self.incident_edge_id)
+    }
+
+    fn read_mdl(r: &mut MdlReader) -> Result<Self, MdlError> {
+        let mut v: Vertex = Default::default();
+        for p in v.position.iter_mut() {
+            *p = r.read_f32()?;
+        }
+        for n in v.normal.iter_mut() {
+            *n = r.read_i16()?;
+        }
+        for t in v.uv.iter_mut() {
+            *t = r.read_u16()?;
+        }
+        r.read_bytes(&mut v.color)?;
+        v.material = r.read_u8()?;
+        r.read_bytes(&mut v.boneid)?;
+        r.read_bytes(&mut v.boneweight)?;
+        v.incident_edge_id = r.read_u16()?;
+        Ok(v)
+    }
+}
+
+impl Face {
+    pub fn new(vertex_ids: [u32; 3]) -> Self {
+        Face { vertex_ids }
+    }
+
+    pub fn vertex_ids(&self) -> [u32; 3] {
+        self.vertex_ids
+    }
+
+    /// Callers check that every id fits in a u16 first.
+    fn write_mdl<W: Write>(&self, w: &mut MdlWriter<W>) -> Result<(), MdlError> {
+        for &i in self.vertex_ids.iter() {
+            w.write_u16(i as u16)?;
+        }
+        Ok(())
+    }
+
+    fn read_mdl(r: &mut MdlReader) -> Result<Self, MdlError> {
+        let mut f: Face = Default::default();
+        for i in f.vertex_ids.iter_mut() {
+            *i = r.read_u16()? as u32;
+        }
+        Ok(f)
+    }
+}
+
+/// Marks a missing face or edge link in an `Edge`.
+pub const NO_ID: u16 = 0xffff;
+
+impl Edge {
+    pub fn vertex_ids(&self) -> [u16; 2] {
+        self.vertex_ids
+    }
+
+    pub fn face_ids(&self) -> [u16; 2] {
+        self.face_ids
+    }
+
+    pub fn first_edges(&self) -> [u16; 2] {
+        self.first_edges
+    }
+
+    pub fn second_edges(&self) -> [u16; 2] {
+        self.second_edges
+    }
+
+    pub fn is_boundary(&self) -> bool {
+        self.face_ids.contains(&NO_ID)
+    }
+
+    /// The face across this edge from `face`, if there is one.
+    pub fn other_face(&self, face: u16) -> Option<u16> {
+        let other = if self.face_ids[0] == face {
+            self.face_ids[1]
+        } else if self.face_ids[1] == face {
+            self.face_ids[0]
+        } else {
+            NO_ID
+        };
+        if other == NO_ID {
+            None
+        } else {
+            Some(other)
+        }
+    }
+
+    fn write_mdl<W: Write>(&self, w: &mut MdlWriter<W>) -> Result<(), MdlError> {
+        for &i in self
+            .vertex_ids
+            .iter()
+            .chain(self.face_ids.iter())
+            .chain(self.first_edges.iter())
+            .chain(self.second_edges.iter())
+        {
+            w.write_u16(i)?;
+        }
+        Ok(())
+    }
+
+    fn read_mdl(r: &mut MdlReader) -> Result<Self, MdlError> {
+        let mut e: Edge = Default::default();
+        for i in e
+            .vertex_ids
+            .iter_mut()
+            .chain(e.face_ids.iter_mut())
+            .chain(e.first_edges.iter_mut())
+            .chain(e.second_edges.iter_mut())
+        {
+            *i = r.read_u16()?;
+        }
+        Ok(e)
+    }
+}
+
+/// Geometry kept on the CPU, independent of any GL context.
+///
+/// This is what the loaders produce; `Mesh::from_data` uploads it.
+#[derive(Default, Debug, Clone)]
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<Vertex>,
+    pub faces: Vec<Face>,
+    pub edges: Vec<Edge>,
+    pub nbones: u8,
+    pub morph_targets: Vec<MorphTarget>,
+    /// Indexed by `Vertex::material`.
+    pub materials: Vec<Material>,
+    /// Face ranges per material, filled by `group_by_material` or by hand.
+    /// Empty when the whole mesh is drawn at once. Passes that reorder or
+    /// drop faces keep every face within its range.
+    pub submeshes: Vec<SubMesh>,
+}
+
+impl MeshData {
+    pub fn new(name: &str) -> Self {
+        MeshData {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Parses a .mdl stream without touching GL.
+    ///
+    /// Every count in the header is checked against the `MDL_MAX_*` limits and
+    /// every face is checked against the vertex count, so a truncated or
+    /// hostile file produces an `MdlError` rather than a panic or a huge
+    /// allocation.
+    pub fn from_mdl(f: &mut dyn Read) -> Result<Self, MdlError> {
+        let mut r = MdlReader::new(f);
+        let header = MdlHeader::read(&mut r)?;
+
+        r.section = MdlSection::Vertices;
+        let nverts = header.nverts as usize;
+        let mut vertices = Vec::with_capacity(nverts.min(MDL_MAX_PREALLOC));
+        for _ in 0..nverts {
+            vertices.push(Vertex::read_mdl(&mut r)?);
+        }
+
+        r.section = MdlSection::Faces;
+        let nfaces = header.nfaces as usize;
+        let mut faces = Vec::with_capacity(nfaces.min(MDL_MAX_PREALLOC));
+        for i in 0..nfaces {
+            let face = Face::read_mdl(&mut r)?;
+            if let Some(&v) = face.vertex_ids.iter().find(|&&v| v as usize >= nverts) {
+                return Err(MdlError::VertexOutOfRange {
+                    face: i,
+                    vertex: v,
+                    nverts,
+                });
+            }
+            faces.push(face);
+        }
+
+        r.section = MdlSection::Edges;
+        let nedges = header.nedges as usize;
+        let mut edges = Vec::with_capacity(nedges.min(MDL_MAX_PREALLOC));
+        for _ in 0..nedges {
+            edges.push(Edge::read_mdl(&mut r)?);
+        }
+
+        let mut morph_targets = Vec::new();
+        if header.version >= 5 {
+            r.section = MdlSection::MorphTargets;
+            let ntargets = r.read_count(MdlSection::MorphTargets, MDL_MAX_MORPH_TARGETS)?;
+            for _ in 0..ntargets {
+                morph_targets.push(MorphTarget::read_mdl(&mut r, nverts)?);
+            }
+        }
+
+        let mut materials = Vec::new();
+        let mut submeshes = Vec::new();
+        if header.version >= 6 {
+            r.section = MdlSection::Materials;
+            let nmaterials = r.read_count(MdlSection::Materials, MDL_MAX_MATERIALS)?;
+            for _ in 0..nmaterials {
+                materials.push(Material::read_mdl(&mut r)?);
+            }
+
+            r.section = MdlSection::SubMeshes;
+            let nsubmeshes = r.read_count(MdlSection::SubMeshes, MDL_MAX_SUBMESHES)?;
+            for i in 0..nsubmeshes as usize {
+                let submesh = SubMesh::read_mdl(&mut r)?;
+                if !submesh.fits(nfaces) {
+                    return Err(MdlError::BadSubMesh(i));
+                }
+                submeshes.push(submesh);
+            }
+        }
+
+        Ok(MeshData {
+            name: header.name(),
+            vertices,
+            faces,
+            edges,
+            nbones: header.nbones,
+            morph_targets,
+            materials,
+            submeshes,
+        })
+    }
+
+    /// Writes the same layout `from_mdl` reads, in the oldest version that
+    /// holds everything so older readers still load plain meshes. Fails
+    /// without writing anything if the name or any section would not fit
+    /// the format.
+    pub fn write_mdl(&self, w: &mut impl Write) -> Result<(), MdlError> {
+        let mut header = MdlHeader {
+            magic: *b"MDL",
+            version: MDL_MIN_VERSION,
+            nbones: self.nbones,
+            name: pack_mdl_name(&self.name)?,
+            ..Default::default()
+        };
+        if !self.materials.is_empty() || !self.submeshes.is_empty() {
+            header.version = 6;
+        } else if !self.morph_targets.is_empty() {
+            header.version = 5;
+        }
+
+        header.nverts =
+            check_mdl_count(MdlSection::Vertices, self.vertices.len(), MDL_MAX_VERTICES)?;
+        header.nfaces = check_mdl_count(MdlSection::Faces, self.faces.len(), MDL_MAX_FACES)?;
+        header.nedges = check_mdl_count(MdlSection::Edges, self.edges.len(), MDL_MAX_EDGES)?;
+        let nverts = self.vertices.len();
+        for (i, face) in self.faces.iter().enumerate() {
+            if let Some(&v) = face.vertex_ids.iter().find(|&&v| v as usize >= nverts) {
+                return Err(MdlError::VertexOutOfRange {
+                    face: i,
+                    vertex: v,
+                    nverts,
+                });
+            }
+        }
+        let ntargets = check_mdl_count(
+            MdlSection::MorphTargets,
+            self.morph_targets.len(),
+            MDL_MAX_MORPH_TARGETS,
+        )?;
+        for (i, target) in self.morph_targets.iter().enumerate() {
+            pack_mdl_name(&target.name)?;
+            if !target.fits(nverts) {
+                return Err(MdlError::BadMorphTarget(i));
+            }
+        }
+        let nmaterials = check_mdl_count(
+            MdlSection::Materials,
+            self.materials.len(),
+            MDL_MAX_MATERIALS,
+        )?;
+        for m in self.materials.iter() {
+            check_mdl_string(&m.name)?;
+            if let Some(texture) = &m.texture {
+                check_mdl_string(texture)?;
+            }
+        }
+        let nsubmeshes = check_mdl_count(
+            MdlSection::SubMeshes,
+            self.submeshes.len(),
+            MDL_MAX_SUBMESHES,
+        )?;
+        for (i, submesh) in self.submeshes.iter().enumerate() {
+            if !submesh.fits(self.faces.len()) {
+                return Err(MdlError::BadSubMesh(i));
+            }
+        }
+
+        let mut w = MdlWriter { w };
+        header.write(&mut w)?;
+        for v in self.vertices.iter() {
+            v.write_mdl(&mut w)?;
+        }
+        for f in self.faces.iter() {
+            f.write_mdl(&mut w)?;
+        }
+        for e in self.edges.iter() {
+            e.write_mdl(&mut w)?;
+        }
+        if header.version >= 5 {
+            w.write_u32(ntargets)?;
+            for target in self.morph_targets.iter() {
+                target.write_mdl(&mut w)?;
+            }
+        }
+        if header.version >= 6 {
+            w.write_u32(nmaterials)?;
+            for m in self.materials.iter() {
+                m.write_mdl(&mut w)?;
+            }
+            w.write_u32(nsubmeshes)?;
+            for submesh in self.submeshes.iter() {
+                submesh.write_mdl(&mut w)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A line list with each edge of the faces once, for drawing the mesh
+    /// as a wireframe with `Primitive::Lines`.
+    pub fn wireframe_indices(&self) -> Vec<u32> {
+        let mut seen = std::collections::HashSet::new();
+        let mut lines = Vec::new();
+        for f in self.faces.iter() {
+            let [a, b, c] = f.vertex_ids;
+            for &(u, v) in [(a, b), (b, c), (c, a)].iter() {
+                if seen.insert((u.min(v), u.max(v))) {
+                    lines.push(u);
+                    lines.push(v);
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// Width of the vertex ids in a mesh's index buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IndexType {
+    U8,
+    U16,
+    U32,
+}
+
+impl IndexType {
+    /// The narrowest type that can index `nverts` vertices.
+    pub fn for_vertex_count(nverts: usize) -> Self {
+        if nverts <= 1 << 8 {
+            IndexType::U8
+        } else if nverts <= 1 << 16 {
+            IndexType::U16
+        } else {
+            IndexType::U32
+        }
+    }
+
+    pub fn gl_type(self) -> GLenum {
+        match self {
+            IndexType::U8 => gl::UNSIGNED_BYTE,
+            IndexType::U16 => gl::UNSIGNED_SHORT,
+            IndexType::U32 => gl::UNSIGNED_INT,
+        }
+    }
+
+    /// Size of one index in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            IndexType::U8 => 1,
+            IndexType::U16 => 2,
+            IndexType::U32 => 4,
+        }
+    }
+
+    /// The narrowest type that can hold every id in `indices`.
+    fn for_indices(indices: &[u32]) -> Self {
+        let max = indices.iter().max();
+        Self::for_vertex_count(max.map_or(0, |&m| m as usize + 1))
+    }
+
+    /// Converts vertex ids to index buffer contents, truncating ids that do
+    /// not fit.
+    fn pack(self, indices: &[u32]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(indices.len() * self.size());
+        for &i in indices.iter() {
+            match self {
+                IndexType::U8 => out.push(i as u8),
+                IndexType::U16 => out.extend_from_slice(&(i as u16).to_ne_bytes()),
+                IndexType::U32 => out.extend_from_slice(&i.to_ne_bytes()),
+            }
+        }
+        out
+    }
+}
+
+fn face_indices(faces: &[Face]) -> Vec<u32> {
+    faces
+        .iter()
+        .flat_map(|f| f.vertex_ids.iter().cloned())
+        .collect()
+}
+
+/// How vertices taken from the index buffer are assembled when drawing.
+///
+/// The adjacency modes feed geometry shaders, which GLES 3.0 and WebGL 2
+/// lack, so they only exist on desktop GL.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Primitive {
+    Points,
+    Lines,
+    LineLoop,
+    LineStrip,
+    Triangles,
+    TriangleStrip,
+    TriangleFan,
+    #[cfg(not(target_os = "emscripten"))]
+    LinesAdjacency,
+    #[cfg(not(target_os = "emscripten"))]
+    LineStripAdjacency,
+    #[cfg(not(target_os = "emscripten"))]
+    TrianglesAdjacency,
+    #[cfg(not(target_os = "emscripten"))]
+    TriangleStripAdjacency,
+}
+
+impl Primitive {
+    pub fn gl_mode(self) -> GLenum {
+        match self {
+            Primitive::Points => gl::POINTS,
+            Primitive::Lines => gl::LINES,
+            Primitive::LineLoop => gl::LINE_LOOP,
+            Primitive::LineStrip => gl::LINE_STRIP,
+            Primitive::Triangles => gl::TRIANGLES,
+            Primitive::TriangleStrip => gl::TRIANGLE_STRIP,
+            Primitive::TriangleFan => gl::TRIANGLE_FAN,
+            #[cfg(not(target_os = "emscripten"))]
+            Primitive::LinesAdjacency => gl::LINES_ADJACENCY,
+            #[cfg(not(target_os = "emscripten"))]
+            Primitive::LineStripAdjacency => gl::LINE_STRIP_ADJACENCY,
+            #[cfg(not(target_os = "emscripten"))]
+            Primitive::TrianglesAdjacency => gl::TRIANGLES_ADJACENCY,
+            #[cfg(not(target_os = "emscripten"))]
+            Primitive::TriangleStripAdjacency => gl::TRIANGLE_STRIP_ADJACENCY,
+        }
+    }
+}
+
+/// How often a mesh's buffers are expected to change.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BufferUsage {
+    /// Uploaded once, drawn many times.
+    Static,
+    /// Changed now and then, drawn many times.
+    Dynamic,
+    /// Rewritten about every time it is drawn.
+    Stream,
+}
+
+impl BufferUsage {
+    pub fn gl_usage(self) -> GLenum {
+        match self {
+            BufferUsage::Static => gl::STATIC_DRAW,
+            BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+            BufferUsage::Stream => gl::STREAM_DRAW,
+        }
+    }
+
+    /// Buffer size in bytes to allocate for `needed` bytes of data when
+    /// `current` bytes are allocated. Changing buffers grow geometrically
+    /// so that appending does not reallocate every time.
+    fn grow(self, current: usize, needed: usize) -> usize {
+        match self {
+            BufferUsage::Static => needed,
+            _ if needed <= current => current,
+            _ => needed.max(current * 2),
+        }
+    }
 }
 
 pub struct Mesh {
@@ -54,6 +1098,23 @@ pub struct Mesh {
     vbo: GLuint,
     vao: GLuint,
     pub nelems: usize,
+    usage: BufferUsage,
+    index_type: IndexType,
+    primitive: Primitive,
+    nverts: usize,
+    /// Allocated sizes of the vertex and index buffers, in bytes.
+    vbo_capacity: usize,
+    ibo_capacity: usize,
+    /// Per-instance attributes; 0 until `upload_instances` creates it.
+    instance_vbo: GLuint,
+    instance_capacity: usize,
+    ninstances: usize,
+    /// Morph deltas; 0 until `upload_morph_deltas` creates it.
+    morph_vbo: GLuint,
+    morph_capacity: usize,
+    aabb: Aabb,
+    bounding_sphere: BoundingSphere,
+    submeshes: Vec<SubMesh>,
 }
 
 impl Drop for Mesh {
@@ -62,35 +1123,49 @@ impl Drop for Mesh {
             dbg!("drop mesh");
             gl::DeleteBuffers(1, &self.vbo);
             gl::DeleteBuffers(1, &self.ibo);
+            if self.instance_vbo != 0 {
+                gl::DeleteBuffers(1, &self.instance_vbo);
+            }
+            if self.morph_vbo != 0 {
+                gl::DeleteBuffers(1, &self.morph_vbo);
+            }
         }
     }
 }
 
-fn read_into<T>(f: &mut Read, s: &mut T) -> std::io::Result<()> {
-    unsafe {
-        let slice =
-            std::slice::from_raw_parts_mut((s as *mut T) as *mut u8, std::mem::size_of::<T>());
-        f.read_exact(slice)?;
-        Ok(())
-    }
-}
-
-fn read_into_slice<T>(f: &mut Read, s: &mut [T]) -> std::io::Result<()>
-where
-    T: std::fmt::Debug,
-{
-    unsafe {
-        let slice = std::slice::from_raw_parts_mut(
-            (s.as_ptr() as *mut T) as *mut u8,
-            std::mem::size_of::<T>() * s.len(),
-        );
-        f.read_exact(slice)?;
-        Ok(())
+/// Writes `bytes` to the start of the buffer bound to `target`, growing it
+/// if needed. A buffer that is big enough is orphaned first, unless static,
+/// so the driver need not wait for draws still reading the old contents.
+unsafe fn fill_buffer(
+    target: GLenum,
+    usage: BufferUsage,
+    capacity: &mut usize,
+    data: *const GLvoid,
+    bytes: usize,
+) {
+    let size = usage.grow(*capacity, bytes);
+    if size != *capacity || usage == BufferUsage::Static {
+        *capacity = size;
+        if size == bytes {
+            gl::BufferData(target, size as GLsizeiptr, data, usage.gl_usage());
+            return;
+        }
     }
+    gl::BufferData(
+        target,
+        *capacity as GLsizeiptr,
+        std::ptr::null(),
+        usage.gl_usage(),
+    );
+    gl::BufferSubData(target, 0, bytes as GLsizeiptr, data);
 }
 
 impl Mesh {
     pub fn new() -> Self {
+        Self::with_usage(BufferUsage::Static)
+    }
+
+    pub fn with_usage(usage: BufferUsage) -> Self {
         let mut vbo: GLuint = 0;
         let mut ibo: GLuint = 0;
         let mut vao: GLuint = 0;
@@ -101,83 +1176,230 @@ impl Mesh {
         }
 
         Mesh {
-            vbo: vbo,
-            ibo: ibo,
-            vao: vao,
+            vbo,
+            ibo,
+            vao,
             nelems: 0,
+            usage,
+            index_type: IndexType::U16,
+            primitive: Primitive::Triangles,
+            nverts: 0,
+            vbo_capacity: 0,
+            ibo_capacity: 0,
+            instance_vbo: 0,
+            instance_capacity: 0,
+            ninstances: 0,
+            morph_vbo: 0,
+            morph_capacity: 0,
+            aabb: Aabb::empty(),
+            bounding_sphere: BoundingSphere::default(),
+            submeshes: Vec::new(),
         }
     }
 
-    pub fn from_mdl(f: &mut Read) -> Result<Self, String> {
-        let mut header: MdlHeader = Default::default();
-        read_into(f, &mut header).unwrap();
+    pub fn from_mdl(f: &mut dyn Read) -> Result<Self, MdlError> {
+        Ok(Self::from_data(&MeshData::from_mdl(f)?))
+    }
 
-        if String::from_utf8_lossy(&header.magic[0..3]) != "MDL" {
-            return Err(format!(
-                "Bad header in .mdl file: {:?}",
-                String::from_utf8_lossy(&header.magic[0..2])
-            ));
-        }
+    pub fn from_data(data: &MeshData) -> Self {
+        let mut mesh = Self::new();
+        mesh.upload_vertices(&data.vertices);
+        mesh.upload_faces(&data.faces);
+        mesh.set_bounds(data.aabb(), data.bounding_sphere());
+        mesh.set_submeshes(data.submeshes.clone());
+        mesh
+    }
 
-        let mut verts: Vec<Vertex> = Vec::with_capacity(header.nverts as usize);
-        let mut faces: Vec<Face> = Vec::with_capacity(header.nfaces as usize);
-        unsafe {
-            verts.set_len(header.nverts as usize);
-            faces.set_len(header.nfaces as usize);
-            read_into_slice(f, verts.as_mut_slice()).unwrap();
-            read_into_slice(f, faces.as_mut_slice()).unwrap();
-        }
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
 
-        let mut mesh = Self::new();
-        mesh.upload_vertex_data(verts);
-        mesh.upload_face_data(faces);
-        Ok(mesh)
+    /// Applies to the next upload.
+    pub fn set_usage(&mut self, usage: BufferUsage) {
+        self.usage = usage;
+    }
+
+    pub fn nverts(&self) -> usize {
+        self.nverts
     }
 
     pub fn upload_vertex_data(&mut self, verts: Vec<Vertex>) {
+        self.upload_vertices(&verts);
+        self.set_bounds(
+            Aabb::from_vertices(&verts),
+            BoundingSphere::from_vertices(&verts),
+        );
+    }
+
+    /// Bounds in model space, computed when `Vertex` data is loaded.
+    /// Other vertex types leave them as they were.
+    pub fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.bounding_sphere
+    }
+
+    /// Overrides the bounds, e.g. after uploading another vertex type or
+    /// to cover every frame of an animation.
+    pub fn set_bounds(&mut self, aabb: Aabb, sphere: BoundingSphere) {
+        self.aabb = aabb;
+        self.bounding_sphere = sphere;
+    }
+
+    /// Uploads vertices of any type, pointing the VAO at the attributes
+    /// its layout describes.
+    pub fn upload_vertices<V: VertexLayout>(&mut self, verts: &[V]) {
+        let stride = layout::upload_stride::<V>();
         unsafe {
             gl::BindVertexArray(self.vao);
 
             gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-            gl::BufferData(
+            fill_buffer(
                 gl::ARRAY_BUFFER,
-                (std::mem::size_of::<Vertex>() * verts.len()) as GLsizeiptr,
+                self.usage,
+                &mut self.vbo_capacity,
                 verts.as_ptr() as *const GLvoid,
-                gl::STATIC_DRAW,
+                std::mem::size_of_val(verts),
             );
 
-            // pos
-            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 32, std::ptr::null());
-            gl::EnableVertexAttribArray(0);
-            // norm
-            gl::VertexAttribPointer(1, 3, gl::SHORT, gl::TRUE, 32, 12 as *const _);
-            gl::EnableVertexAttribArray(1);
-            // uv
-            gl::VertexAttribPointer(2, 2, gl::UNSIGNED_SHORT, gl::TRUE, 32, 18 as *const _);
-            gl::EnableVertexAttribArray(2);
-            // color
-            gl::VertexAttribPointer(3, 3, gl::UNSIGNED_BYTE, gl::TRUE, 32, 22 as *const _);
-            gl::EnableVertexAttribArray(3);
+            for attrib in V::attributes() {
+                attrib.enable(stride);
+            }
 
             gl::BindVertexArray(0);
             gl::BindBuffer(gl::ARRAY_BUFFER, 0);
         }
+        self.nverts = verts.len();
+    }
+
+    /// Overwrites vertices `first..first + verts.len()` in place. `V` must
+    /// be the type last passed to `upload_vertices`.
+    pub fn update_vertices<V: VertexLayout>(&mut self, first: usize, verts: &[V]) {
+        assert!(
+            first + verts.len() <= self.nverts,
+            "vertex update out of range"
+        );
+        let stride = layout::upload_stride::<V>();
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                (stride * first) as GLintptr,
+                std::mem::size_of_val(verts) as GLsizeiptr,
+                verts.as_ptr() as *const GLvoid,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    /// Grows the vertex buffer to hold at least `n` vertices of type `V`,
+    /// so later uploads up to that size only write data. The current
+    /// vertices are discarded if the buffer has to grow.
+    pub fn reserve_vertices<V: VertexLayout>(&mut self, n: usize) {
+        let bytes = layout::upload_stride::<V>() * n;
+        if bytes <= self.vbo_capacity {
+            return;
+        }
+        self.vbo_capacity = self.usage.grow(self.vbo_capacity, bytes);
+        self.nverts = 0;
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                self.vbo_capacity as GLsizeiptr,
+                std::ptr::null(),
+                self.usage.gl_usage(),
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
     }
 
     pub fn upload_face_data(&mut self, faces: Vec<Face>) {
+        self.upload_faces(&faces);
+    }
+
+    /// Uploads faces as a triangle list using the narrowest index type that
+    /// can hold every vertex id in them.
+    pub fn upload_faces(&mut self, faces: &[Face]) {
+        self.upload_indices(&face_indices(faces));
+        self.primitive = Primitive::Triangles;
+    }
+
+    /// Uploads raw vertex ids, to be assembled according to `primitive`.
+    pub fn upload_indices(&mut self, indices: &[u32]) {
+        self.index_type = IndexType::for_indices(indices);
+        let packed = self.index_type.pack(indices);
+        unsafe {
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ibo);
+            fill_buffer(
+                gl::ELEMENT_ARRAY_BUFFER,
+                self.usage,
+                &mut self.ibo_capacity,
+                packed.as_ptr() as *const GLvoid,
+                packed.len(),
+            );
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        }
+        self.nelems = indices.len();
+    }
+
+    /// Overwrites faces `first..first + faces.len()` in place. Their vertex
+    /// ids must fit the mesh's current `index_type`.
+    pub fn update_faces(&mut self, first: usize, faces: &[Face]) {
+        self.update_indices(first * 3, &face_indices(faces));
+    }
+
+    /// Overwrites indices `first..first + indices.len()` in place.
+    pub fn update_indices(&mut self, first: usize, indices: &[u32]) {
+        assert!(
+            first + indices.len() <= self.nelems,
+            "index update out of range"
+        );
+        assert!(
+            IndexType::for_indices(indices).size() <= self.index_type.size(),
+            "index update does not fit the index type"
+        );
+        let packed = self.index_type.pack(indices);
+        unsafe {
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ibo);
+            gl::BufferSubData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                (self.index_type.size() * first) as GLintptr,
+                packed.len() as GLsizeiptr,
+                packed.as_ptr() as *const GLvoid,
+            );
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        }
+    }
+
+    /// Grows the index buffer to hold at least `n` faces of the current
+    /// `index_type`. The current faces are discarded if the buffer has to
+    /// grow.
+    pub fn reserve_faces(&mut self, n: usize) {
+        let bytes = self.index_type.size() * 3 * n;
+        if bytes <= self.ibo_capacity {
+            return;
+        }
+        self.ibo_capacity = self.usage.grow(self.ibo_capacity, bytes);
+        self.nelems = 0;
         unsafe {
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ibo);
             gl::BufferData(
                 gl::ELEMENT_ARRAY_BUFFER,
-                (std::mem::size_of::<Face>() * faces.len()) as GLsizeiptr,
-                faces.as_ptr() as *const GLvoid,
-                gl::STATIC_DRAW,
+                self.ibo_capacity as GLsizeiptr,
+                std::ptr::null(),
+                self.usage.gl_usage(),
             );
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
-            self.nelems = faces.len() * 3;
         }
     }
 
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
     pub fn bind(&self) {
         unsafe {
             //gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
@@ -186,14 +1408,402 @@ impl Mesh {
         }
     }
 
+    pub fn primitive(&self) -> Primitive {
+        self.primitive
+    }
+
+    /// Changes how the index buffer is assembled; `upload_faces` resets it
+    /// to `Triangles`.
+    pub fn set_primitive(&mut self, primitive: Primitive) {
+        self.primitive = primitive;
+    }
+
     pub fn draw(&self) {
+        self.draw_range(0, self.nelems);
+    }
+
+    /// Draws `count` indices starting at index `first`.
+    pub fn draw_range(&self, first: usize, count: usize) {
+        assert!(first + count <= self.nelems, "draw range out of range");
+        let offset = (first * self.index_type.size()) as *const GLvoid;
         unsafe {
             gl::DrawElements(
-                gl::TRIANGLES,
-                self.nelems as i32,
-                gl::UNSIGNED_SHORT,
-                std::ptr::null(),
+                self.primitive.gl_mode(),
+                count as GLsizei,
+                self.index_type.gl_type(),
+                offset,
+            );
+        }
+    }
+
+    /// Draws `count` indices starting at index `first`, adding `base_vertex`
+    /// to every index. Lets sub-meshes with their own 0-based indices share
+    /// one vertex and index buffer.
+    ///
+    /// Desktop GL only: GLES 3.0 and WebGL 2 have no base vertex draws.
+    #[cfg(not(target_os = "emscripten"))]
+    pub fn draw_range_base_vertex(&self, first: usize, count: usize, base_vertex: i32) {
+        if base_vertex == 0 {
+            return self.draw_range(first, count);
+        }
+        assert!(first + count <= self.nelems, "draw range out of range");
+        let offset = (first * self.index_type.size()) as *const GLvoid;
+        unsafe {
+            gl::DrawElementsBaseVertex(
+                self.primitive.gl_mode(),
+                count as GLsizei,
+                self.index_type.gl_type(),
+                offset,
+                base_vertex,
             );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    static UNIT_QUAD: &[u8] = include_bytes!("../examples/draw/assets/unit_quad.mdl");
+
+    #[test]
+    fn reads_unit_quad() {
+        let data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        assert_eq!(data.vertices.len(), 4);
+        assert_eq!(data.faces.len(), 2);
+        assert!(data.edges.is_empty());
+        assert_eq!(data.vertices[2].position, [1.0, 1.0, 1.0]);
+        assert_eq!(data.faces[1].vertex_ids, [3, 0, 2]);
+    }
+
+    #[test]
+    fn packs_vertex_attributes() {
+        let v = Vertex::new(
+            [1.0, 2.0, 3.0],
+            [0.0, -1.0, 0.5],
+            [0.25, 1.0],
+            [1.0, 0.5, 0.0],
+        )
+        .with_bones([3, 7], [0.75, 0.25])
+        .with_material(2);
+        assert_eq!(v.position, [1.0, 2.0, 3.0]);
+        assert_eq!(v.normal, [0, -32767, 16384]);
+        assert_eq!(v.uv, [16384, 65535]);
+        assert_eq!(v.color, [255, 128, 0]);
+        assert_eq!(v.boneid, [3, 7]);
+        assert_eq!(v.boneweight, [191, 64]);
+        assert_eq!(v.material(), 2);
+
+        let n = v.normal();
+        assert!((n[1] + 1.0).abs() < 1e-6 && (n[2] - 0.5).abs() < 1e-4);
+        let uv = v.uv();
+        assert!((uv[0] - 0.25).abs() < 1e-4 && uv[1] == 1.0);
+    }
+
+    #[test]
+    fn clamps_out_of_range_attributes() {
+        let v = Vertex::new([0.0; 3], [2.0, -2.0, 0.0], [-1.0, 3.0], [4.0, -1.0, 0.0]);
+        assert_eq!(v.normal, [32767, -32767, 0]);
+        assert_eq!(v.uv, [0, 65535]);
+        assert_eq!(v.color, [255, 0, 0]);
+    }
+
+    #[test]
+    fn round_trips_unit_quad() {
+        let data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        let mut out = Vec::new();
+        data.write_mdl(&mut out).unwrap();
+        assert_eq!(out.as_slice(), UNIT_QUAD);
+    }
+
+    #[test]
+    fn round_trips_built_mesh() {
+        let mut data = MeshData::new("triangle");
+        data.nbones = 2;
+        data.vertices = vec![
+            Vertex::new(
+                [0.0, 0.0, 0.0],
+                [0.0, 0.0, -1.0],
+                [0.0, 0.0],
+                [1.0, 0.0, 0.0],
+            ),
+            Vertex::new(
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, -1.0],
+                [1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            )
+            .with_bones([1, 0], [1.0, 0.0]),
+            Vertex::new(
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, -1.0],
+                [0.0, 1.0],
+                [0.0, 0.0, 1.0],
+            )
+            .with_material(3),
+        ];
+        data.faces = vec![Face::new([0, 2, 1])];
+        data.edges = vec![Edge {
+            vertex_ids: [0, 2],
+            face_ids: [0, 0xffff],
+            first_edges: [1, 0xffff],
+            second_edges: [2, 0xffff],
+        }];
+
+        let mut out = Vec::new();
+        data.write_mdl(&mut out).unwrap();
+        assert_eq!(out.len(), 32 + 3 * 32 + 6 + 16);
+
+        let read = MeshData::from_mdl(&mut Cursor::new(&out)).unwrap();
+        assert_eq!(read.name, "triangle");
+        assert_eq!(read.nbones, 2);
+        assert_eq!(read.vertices, data.vertices);
+        assert_eq!(read.faces, data.faces);
+        assert_eq!(read.edges, data.edges);
+    }
+
+    #[test]
+    fn round_trips_morph_targets() {
+        let mut data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        let mut blink = MorphTarget::new("blink", 4);
+        blink.positions[3] = [0.0, -1.0, 0.0];
+        blink.normals = vec![[0.0, 0.0, 0.5]; 4];
+        data.morph_targets = vec![blink, MorphTarget::new("", 4)];
+
+        let mut out = Vec::new();
+        data.write_mdl(&mut out).unwrap();
+        assert_eq!(out[3], 5);
+        assert_eq!(out.len(), UNIT_QUAD.len() + 4 + (16 + 96) + (16 + 48));
+        let read = MeshData::from_mdl(&mut Cursor::new(&out)).unwrap();
+        assert_eq!(read.morph_targets, data.morph_targets);
+
+        match MeshData::from_mdl(&mut Cursor::new(&out[..out.len() - 1])) {
+            Err(MdlError::Truncated(MdlSection::MorphTargets)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        data.morph_targets[1].positions.pop();
+        match data.write_mdl(&mut Vec::new()) {
+            Err(MdlError::BadMorphTarget(1)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn round_trips_materials() {
+        let mut data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        let mut brick = Material::new("brick");
+        brick.base_color = [0.5, 0.25, 0.0, 1.0];
+        brick.texture = Some("textures/brick.png".to_string());
+        data.materials = vec![Material::new(""), brick];
+        data.submeshes = vec![
+            SubMesh {
+                material: 0,
+                first_face: 0,
+                nfaces: 1,
+            },
+            SubMesh {
+                material: 1,
+                first_face: 1,
+                nfaces: 1,
+            },
+        ];
+
+        let mut out = Vec::new();
+        data.write_mdl(&mut out).unwrap();
+        assert_eq!(out[3], 6);
+        assert_eq!(
+            out.len(),
+            UNIT_QUAD.len() + 4 + 4 + (1 + 16 + 1) + (6 + 16 + 2 + 18) + 4 + 2 * 9
+        );
+        let read = MeshData::from_mdl(&mut Cursor::new(&out)).unwrap();
+        assert_eq!(read.materials, data.materials);
+        assert_eq!(read.submeshes, data.submeshes);
+        assert!(read.morph_targets.is_empty());
+
+        match MeshData::from_mdl(&mut Cursor::new(&out[..out.len() - 1])) {
+            Err(MdlError::Truncated(MdlSection::SubMeshes)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        let mut bad = out.clone();
+        let last = bad.len() - 5;
+        bad[last..last + 4].copy_from_slice(&2u32.to_le_bytes());
+        match MeshData::from_mdl(&mut Cursor::new(&bad)) {
+            Err(MdlError::BadSubMesh(1)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        data.materials[1].texture = Some("x".repeat(MDL_MAX_STRING_LEN + 1));
+        match data.write_mdl(&mut Vec::new()) {
+            Err(MdlError::StringTooLong(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn refuses_to_write_unrepresentable_mesh() {
+        let data = MeshData::new("a name that is far too long");
+        let mut out = Vec::new();
+        match data.write_mdl(&mut out) {
+            Err(MdlError::NameTooLong(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut data = MeshData::new("big");
+        data.vertices = vec![Default::default(); MDL_MAX_VERTICES as usize + 1];
+        match data.write_mdl(&mut out) {
+            Err(MdlError::TooLarge {
+                section: MdlSection::Vertices,
+                ..
+            }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        data.vertices.truncate(3);
+        data.faces = vec![Face::new([0, 1, 2]), Face::new([0, 2, 70000])];
+        match data.write_mdl(&mut out) {
+            Err(MdlError::VertexOutOfRange {
+                face: 1,
+                vertex: 70000,
+                nverts: 3,
+            }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut data = UNIT_QUAD.to_vec();
+        data[0] = b'X';
+        match MeshData::from_mdl(&mut Cursor::new(data)) {
+            Err(MdlError::BadMagic(m)) => assert_eq!(&m, b"XDL"),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut data = UNIT_QUAD.to_vec();
+        data[3] = 99;
+        match MeshData::from_mdl(&mut Cursor::new(data)) {
+            Err(MdlError::UnsupportedVersion(99)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reports_truncated_section() {
+        let cases = [
+            (10, MdlSection::Header),
+            (32 + 40, MdlSection::Vertices),
+            (UNIT_QUAD.len() - 1, MdlSection::Faces),
+        ];
+        for &(len, section) in cases.iter() {
+            match MeshData::from_mdl(&mut Cursor::new(&UNIT_QUAD[..len])) {
+                Err(MdlError::Truncated(s)) => assert_eq!(s, section),
+                r => panic!("unexpected result {:?}", r.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_edge_section() {
+        let mut data = UNIT_QUAD.to_vec();
+        data[12..16].copy_from_slice(&1u32.to_le_bytes());
+        match MeshData::from_mdl(&mut Cursor::new(&data)) {
+            Err(MdlError::Truncated(MdlSection::Edges)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        data.extend_from_slice(&[0, 0, 1, 0, 0, 0, 255, 255, 1, 0, 2, 0, 0, 0, 0, 0]);
+        let mesh = MeshData::from_mdl(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(mesh.edges.len(), 1);
+        assert_eq!(mesh.edges[0].vertex_ids, [0, 1]);
+        assert_eq!(mesh.edges[0].face_ids, [0, 0xffff]);
+        assert_eq!(mesh.edges[0].first_edges, [1, 2]);
+    }
+
+    #[test]
+    fn rejects_huge_counts_without_allocating() {
+        let mut data = UNIT_QUAD.to_vec();
+        data[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        match MeshData::from_mdl(&mut Cursor::new(data)) {
+            Err(MdlError::TooLarge {
+                section: MdlSection::Vertices,
+                ..
+            }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        // Within limits but far larger than the data actually present.
+        let mut data = UNIT_QUAD.to_vec();
+        data[8..12].copy_from_slice(&MDL_MAX_FACES.to_le_bytes());
+        match MeshData::from_mdl(&mut Cursor::new(data)) {
+            Err(MdlError::Truncated(MdlSection::Faces)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_face() {
+        let mut data = UNIT_QUAD.to_vec();
+        let last = data.len() - 2;
+        data[last..].copy_from_slice(&7u16.to_le_bytes());
+        match MeshData::from_mdl(&mut Cursor::new(data)) {
+            Err(MdlError::VertexOutOfRange {
+                face: 1,
+                vertex: 7,
+                nverts: 4,
+            }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn arbitrary_input_never_panics() {
+        // Cheap stand-in for a fuzzer: every prefix and a spread of single
+        // byte corruptions of a real file must parse or fail cleanly.
+        for len in 0..UNIT_QUAD.len() {
+            let _ = MeshData::from_mdl(&mut Cursor::new(&UNIT_QUAD[..len]));
+        }
+        for i in 0..UNIT_QUAD.len() {
+            let mut data = UNIT_QUAD.to_vec();
+            data[i] = data[i].wrapping_add(0x81);
+            let _ = MeshData::from_mdl(&mut Cursor::new(data));
+        }
+    }
+
+    #[test]
+    fn grows_changing_buffers_geometrically() {
+        assert_eq!(BufferUsage::Static.grow(100, 40), 40);
+        assert_eq!(BufferUsage::Static.grow(100, 400), 400);
+        assert_eq!(BufferUsage::Dynamic.grow(100, 40), 100);
+        assert_eq!(BufferUsage::Dynamic.grow(100, 120), 200);
+        assert_eq!(BufferUsage::Stream.grow(100, 500), 500);
+        assert_eq!(BufferUsage::Stream.grow(0, 32), 32);
+    }
+
+    #[test]
+    fn picks_narrowest_index_type() {
+        assert_eq!(IndexType::for_vertex_count(0), IndexType::U8);
+        assert_eq!(IndexType::for_vertex_count(256), IndexType::U8);
+        assert_eq!(IndexType::for_vertex_count(257), IndexType::U16);
+        assert_eq!(IndexType::for_vertex_count(65536), IndexType::U16);
+        assert_eq!(IndexType::for_vertex_count(65537), IndexType::U32);
+
+        let indices = face_indices(&[Face::new([1, 2, 70000])]);
+        assert_eq!(IndexType::for_indices(&indices), IndexType::U32);
+        assert_eq!(IndexType::U8.pack(&indices), vec![1, 2, 70000u32 as u8]);
+        assert_eq!(IndexType::U16.pack(&indices).len(), 6);
+        let wide = IndexType::U32.pack(&indices);
+        assert_eq!(&wide[8..], &70000u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn builds_wireframe_indices() {
+        let data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        assert_eq!(data.wireframe_indices(), vec![0, 1, 1, 2, 2, 0, 3, 0, 2, 3]);
+    }
+}
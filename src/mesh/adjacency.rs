@@ -0,0 +1,405 @@
+use super::{Edge, MeshData, MDL_MAX_VERTICES, NO_ID};
+use std::collections::HashMap;
+use std::{error, fmt};
+
+#[derive(Debug, PartialEq)]
+pub enum AdjacencyError {
+    /// Vertex, edge and face ids are u16 with `NO_ID` reserved for the
+    /// latter two, so larger meshes cannot carry winged-edge data.
+    TooManyElements,
+    MissingEdges,
+    EdgeOutOfRange(usize),
+    DanglingEdge(usize),
+    DuplicateEdge(usize),
+    NonManifoldEdge(usize),
+    FaceEdgeMissing(usize),
+    EdgeNotInFace {
+        edge: usize,
+        face: usize,
+    },
+    BadWing {
+        edge: usize,
+        face: usize,
+    },
+    BadIncidentEdge(usize),
+}
+
+impl fmt::Display for AdjacencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdjacencyError::TooManyElements => {
+                write!(f, "mesh is too large for 16 bit edge and face ids")
+            }
+            AdjacencyError::MissingEdges => write!(f, "mesh has faces but no edge data"),
+            AdjacencyError::EdgeOutOfRange(e) => write!(f, "edge {} has an out of range id", e),
+            AdjacencyError::DanglingEdge(e) => write!(f, "edge {} borders no face", e),
+            AdjacencyError::DuplicateEdge(e) => write!(f, "edge {} is listed more than once", e),
+            AdjacencyError::NonManifoldEdge(e) => {
+                write!(f, "edge {} is shared by more than two faces", e)
+            }
+            AdjacencyError::FaceEdgeMissing(face) => {
+                write!(f, "face {} has a side with no matching edge", face)
+            }
+            AdjacencyError::EdgeNotInFace { edge, face } => {
+                write!(
+                    f,
+                    "edge {} claims face {} which does not contain it",
+                    edge, face
+                )
+            }
+            AdjacencyError::BadWing { edge, face } => {
+                write!(f, "edge {} has wrong wing links for face {}", edge, face)
+            }
+            AdjacencyError::BadIncidentEdge(v) => {
+                write!(
+                    f,
+                    "vertex {} has an incident edge that does not touch it",
+                    v
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for AdjacencyError {}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn face_sides(v: [u32; 3]) -> [(u32, u32); 3] {
+    [(v[0], v[1]), (v[1], v[2]), (v[2], v[0])]
+}
+
+/// The wings of `edge` within a face whose edges are `face_edges`.
+fn wings(edges: &[Edge], edge: usize, face_edges: [u16; 3]) -> (u16, u16) {
+    let [a, b] = edges[edge].vertex_ids;
+    let mut first = NO_ID;
+    let mut second = NO_ID;
+    for &other in face_edges.iter().filter(|&&o| o as usize != edge) {
+        let ov = edges[other as usize].vertex_ids;
+        if ov.contains(&a) {
+            first = other;
+        } else if ov.contains(&b) {
+            second = other;
+        }
+    }
+    (first, second)
+}
+
+impl MeshData {
+    /// Rebuilds `edges` and each vertex's `incident_edge_id` from the faces.
+    ///
+    /// Useful for meshes built in code or loaded from a file without an edge
+    /// section.
+    pub fn build_edges(&mut self) -> Result<(), AdjacencyError> {
+        if self.faces.len() >= NO_ID as usize || self.vertices.len() > MDL_MAX_VERTICES as usize {
+            return Err(AdjacencyError::TooManyElements);
+        }
+
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut lookup: HashMap<(u32, u32), u16> = HashMap::new();
+        let mut face_edges = vec![[NO_ID; 3]; self.faces.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            for (k, &(a, b)) in face_sides(face.vertex_ids).iter().enumerate() {
+                let id = match lookup.get(&edge_key(a, b)) {
+                    Some(&id) => id,
+                    None => {
+                        if edges.len() >= NO_ID as usize {
+                            return Err(AdjacencyError::TooManyElements);
+                        }
+                        edges.push(Edge {
+                            vertex_ids: [a as u16, b as u16],
+                            face_ids: [NO_ID; 2],
+                            first_edges: [NO_ID; 2],
+                            second_edges: [NO_ID; 2],
+                        });
+                        let id = (edges.len() - 1) as u16;
+                        lookup.insert(edge_key(a, b), id);
+                        id
+                    }
+                };
+
+                let e = &mut edges[id as usize];
+                match e.face_ids.iter().position(|&x| x == NO_ID) {
+                    Some(side) => e.face_ids[side] = f as u16,
+                    None => return Err(AdjacencyError::NonManifoldEdge(id as usize)),
+                }
+                face_edges[f][k] = id;
+            }
+        }
+
+        for e in 0..edges.len() {
+            for side in 0..2 {
+                let face = edges[e].face_ids[side];
+                if face != NO_ID {
+                    let (first, second) = wings(&edges, e, face_edges[face as usize]);
+                    edges[e].first_edges[side] = first;
+                    edges[e].second_edges[side] = second;
+                }
+            }
+        }
+
+        self.edges = edges;
+        self.assign_incident_edges();
+        Ok(())
+    }
+
+    /// Points each vertex's `incident_edge_id` at one of its edges.
+    pub(crate) fn assign_incident_edges(&mut self) {
+        for (i, e) in self.edges.iter().enumerate() {
+            for &v in e.vertex_ids.iter() {
+                if let Some(vert) = self.vertices.get_mut(v as usize) {
+                    vert.incident_edge_id = i as u16;
+                }
+            }
+        }
+    }
+
+    /// Validates the edge section against the faces and returns a view for
+    /// navigating it.
+    pub fn adjacency(&self) -> Result<Adjacency<'_>, AdjacencyError> {
+        Adjacency::new(self)
+    }
+}
+
+/// Connectivity queries over a `MeshData` whose edge data has been validated.
+pub struct Adjacency<'a> {
+    data: &'a MeshData,
+    face_edges: Vec<[u16; 3]>,
+    vertex_edges: Vec<Vec<u16>>,
+}
+
+impl<'a> Adjacency<'a> {
+    pub fn new(data: &'a MeshData) -> Result<Self, AdjacencyError> {
+        let nverts = data.vertices.len();
+        let nfaces = data.faces.len();
+        let nedges = data.edges.len();
+        if nfaces >= NO_ID as usize || nedges >= NO_ID as usize {
+            return Err(AdjacencyError::TooManyElements);
+        }
+        if nedges == 0 && nfaces > 0 {
+            return Err(AdjacencyError::MissingEdges);
+        }
+
+        let in_range = |id: u16, n: usize| id == NO_ID || (id as usize) < n;
+        let mut lookup: HashMap<(u32, u32), u16> = HashMap::new();
+        let mut vertex_edges = vec![Vec::new(); nverts];
+        for (i, e) in data.edges.iter().enumerate() {
+            let [a, b] = e.vertex_ids;
+            if a as usize >= nverts
+                || b as usize >= nverts
+                || a == b
+                || !e.face_ids.iter().all(|&f| in_range(f, nfaces))
+                || !e.first_edges.iter().all(|&l| in_range(l, nedges))
+                || !e.second_edges.iter().all(|&l| in_range(l, nedges))
+            {
+                return Err(AdjacencyError::EdgeOutOfRange(i));
+            }
+            if e.face_ids == [NO_ID; 2] {
+                return Err(AdjacencyError::DanglingEdge(i));
+            }
+            if e.face_ids[0] == e.face_ids[1] {
+                return Err(AdjacencyError::NonManifoldEdge(i));
+            }
+            if lookup
+                .insert(edge_key(a as u32, b as u32), i as u16)
+                .is_some()
+            {
+                return Err(AdjacencyError::DuplicateEdge(i));
+            }
+            vertex_edges[a as usize].push(i as u16);
+            vertex_edges[b as usize].push(i as u16);
+        }
+
+        let mut face_edges = Vec::with_capacity(nfaces);
+        for (f, face) in data.faces.iter().enumerate() {
+            let mut ids = [NO_ID; 3];
+            for (k, &(a, b)) in face_sides(face.vertex_ids).iter().enumerate() {
+                let e = *lookup
+                    .get(&edge_key(a, b))
+                    .ok_or(AdjacencyError::FaceEdgeMissing(f))?;
+                if !data.edges[e as usize].face_ids.contains(&(f as u16)) {
+                    return Err(AdjacencyError::FaceEdgeMissing(f));
+                }
+                ids[k] = e;
+            }
+            face_edges.push(ids);
+        }
+
+        for (i, e) in data.edges.iter().enumerate() {
+            for side in 0..2 {
+                let face = e.face_ids[side];
+                if face == NO_ID {
+                    if e.first_edges[side] != NO_ID || e.second_edges[side] != NO_ID {
+                        return Err(AdjacencyError::BadWing {
+                            edge: i,
+                            face: NO_ID as usize,
+                        });
+                    }
+                    continue;
+                }
+                let face = face as usize;
+                if !face_edges[face].contains(&(i as u16)) {
+                    return Err(AdjacencyError::EdgeNotInFace { edge: i, face });
+                }
+                let (first, second) = wings(&data.edges, i, face_edges[face]);
+                if e.first_edges[side] != first || e.second_edges[side] != second {
+                    return Err(AdjacencyError::BadWing { edge: i, face });
+                }
+            }
+        }
+
+        for (v, edges) in vertex_edges.iter().enumerate() {
+            if !edges.is_empty() && !edges.contains(&data.vertices[v].incident_edge_id) {
+                return Err(AdjacencyError::BadIncidentEdge(v));
+            }
+        }
+
+        Ok(Adjacency {
+            data,
+            face_edges,
+            vertex_edges,
+        })
+    }
+
+    pub fn edge(&self, edge: usize) -> &Edge {
+        &self.data.edges[edge]
+    }
+
+    /// Edges of `face` in winding order, starting with the side from its
+    /// first to its second vertex.
+    pub fn face_edges(&self, face: usize) -> [usize; 3] {
+        let e = self.face_edges[face];
+        [e[0] as usize, e[1] as usize, e[2] as usize]
+    }
+
+    pub fn edges_around_vertex(&self, vertex: usize) -> impl Iterator<Item = usize> + '_ {
+        self.vertex_edges[vertex].iter().map(|&e| e as usize)
+    }
+
+    /// Each face using `vertex`, once, in ascending order.
+    pub fn faces_around_vertex(&self, vertex: usize) -> Vec<usize> {
+        let mut faces: Vec<usize> = self.vertex_edges[vertex]
+            .iter()
+            .flat_map(|&e| self.data.edges[e as usize].face_ids.iter())
+            .filter(|&&f| f != NO_ID)
+            .map(|&f| f as usize)
+            .collect();
+        faces.sort();
+        faces.dedup();
+        faces
+    }
+
+    /// The face across each of `face`'s edges, in `face_edges` order.
+    pub fn neighbour_faces(&self, face: usize) -> [Option<usize>; 3] {
+        let mut ret = [None; 3];
+        for (k, &e) in self.face_edges[face].iter().enumerate() {
+            ret[k] = self.data.edges[e as usize]
+                .other_face(face as u16)
+                .map(|f| f as usize);
+        }
+        ret
+    }
+
+    pub fn boundary_edges(&self) -> impl Iterator<Item = usize> + '_ {
+        self.data
+            .edges
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.is_boundary())
+            .map(|(i, _)| i)
+    }
+
+    pub fn is_boundary_vertex(&self, vertex: usize) -> bool {
+        self.vertex_edges[vertex]
+            .iter()
+            .any(|&e| self.data.edges[e as usize].is_boundary())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Face;
+    use std::io::Cursor;
+
+    static UNIT_QUAD: &[u8] = include_bytes!("../../examples/draw/assets/unit_quad.mdl");
+
+    fn quad() -> MeshData {
+        let mut data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        data.build_edges().unwrap();
+        data
+    }
+
+    #[test]
+    fn builds_quad_edges() {
+        let data = quad();
+        assert_eq!(data.edges.len(), 5);
+        let adj = data.adjacency().unwrap();
+        assert_eq!(adj.boundary_edges().count(), 4);
+        assert_eq!(adj.faces_around_vertex(0), vec![0, 1]);
+        assert_eq!(adj.faces_around_vertex(1), vec![0]);
+        assert_eq!(adj.edges_around_vertex(2).count(), 3);
+        assert!(adj.is_boundary_vertex(3));
+
+        // Faces are (0, 1, 2) and (3, 0, 2); they meet along 2-0.
+        assert_eq!(adj.neighbour_faces(0), [None, None, Some(1)]);
+        assert_eq!(adj.neighbour_faces(1), [None, Some(0), None]);
+        let diagonal = adj.face_edges(0)[2];
+        assert_eq!(adj.face_edges(1)[1], diagonal);
+        assert!(!adj.edge(diagonal).is_boundary());
+    }
+
+    #[test]
+    fn edges_survive_mdl_round_trip() {
+        let data = quad();
+        let mut out = Vec::new();
+        data.write_mdl(&mut out).unwrap();
+        let read = MeshData::from_mdl(&mut Cursor::new(&out)).unwrap();
+        assert_eq!(read.edges, data.edges);
+        assert!(read.adjacency().is_ok());
+    }
+
+    #[test]
+    fn rejects_inconsistent_edges() {
+        let data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        assert_eq!(data.adjacency().err(), Some(AdjacencyError::MissingEdges));
+
+        let mut data = quad();
+        data.edges[0].first_edges[0] = data.edges[0].second_edges[0];
+        assert_eq!(
+            data.adjacency().err(),
+            Some(AdjacencyError::BadWing { edge: 0, face: 0 })
+        );
+
+        let mut data = quad();
+        data.edges[1].face_ids = [1, NO_ID];
+        assert!(data.adjacency().is_err());
+
+        let mut data = quad();
+        data.edges[4].vertex_ids[1] = 40;
+        assert_eq!(
+            data.adjacency().err(),
+            Some(AdjacencyError::EdgeOutOfRange(4))
+        );
+
+        let mut data = quad();
+        data.edges.pop();
+        assert!(data.adjacency().is_err());
+
+        let mut data = quad();
+        data.vertices[1].incident_edge_id = 4;
+        assert_eq!(
+            data.adjacency().err(),
+            Some(AdjacencyError::BadIncidentEdge(1))
+        );
+    }
+
+    #[test]
+    fn rejects_non_manifold_faces() {
+        let mut data = quad();
+        data.faces.push(Face::new([0, 2, 1]));
+        assert_eq!(data.build_edges(), Err(AdjacencyError::NonManifoldEdge(2)));
+    }
+}
@@ -0,0 +1,171 @@
+use super::layout::{upload_stride, AttribType, VertexAttrib, VertexLayout};
+use super::{fill_buffer, Mesh};
+use gl::types::*;
+
+/// Per-instance data for `glsl/instanced.vs`: a model matrix and a color.
+///
+/// Any other `VertexLayout` type works as instance data too, as long as its
+/// locations do not clash with the mesh's vertex attributes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct Instance {
+    /// Column-major, like nalgebra's `Matrix4::as_slice`.
+    pub model: [[f32; 4]; 4],
+    pub color: [f32; 4],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Instance {
+            model: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+            color: [1.0; 4],
+        }
+    }
+}
+
+const fn column(name: &'static str, k: usize) -> VertexAttrib {
+    VertexAttrib {
+        name,
+        location: 7 + k as GLuint,
+        kind: AttribType::Float,
+        count: 4,
+        normalized: false,
+        offset: 16 * k,
+    }
+}
+
+const INSTANCE_ATTRIBUTES: [VertexAttrib; 5] = [
+    column("model", 0),
+    column("", 1),
+    column("", 2),
+    column("", 3),
+    VertexAttrib {
+        name: "instance_color",
+        location: 11,
+        kind: AttribType::Float,
+        count: 4,
+        normalized: false,
+        offset: 64,
+    },
+];
+
+impl VertexLayout for Instance {
+    fn attributes() -> &'static [VertexAttrib] {
+        &INSTANCE_ATTRIBUTES
+    }
+}
+
+impl Mesh {
+    /// Uploads one `I` per instance and points the VAO at its attributes,
+    /// advancing once per instance instead of once per vertex.
+    pub fn upload_instances<I: VertexLayout>(&mut self, instances: &[I]) {
+        let stride = upload_stride::<I>();
+        unsafe {
+            if self.instance_vbo == 0 {
+                gl::GenBuffers(1, &mut self.instance_vbo);
+            }
+            gl::BindVertexArray(self.vao);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.instance_vbo);
+            fill_buffer(
+                gl::ARRAY_BUFFER,
+                self.usage,
+                &mut self.instance_capacity,
+                instances.as_ptr() as *const GLvoid,
+                std::mem::size_of_val(instances),
+            );
+
+            for attrib in I::attributes() {
+                attrib.enable(stride);
+                gl::VertexAttribDivisor(attrib.location, 1);
+            }
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.ninstances = instances.len();
+    }
+
+    pub fn ninstances(&self) -> usize {
+        self.ninstances
+    }
+}
+
+/// Draws a range of a mesh's indices once per instance in a single call.
+///
+/// By default the whole mesh is drawn for every uploaded instance.
+pub struct InstancedDraw<'a> {
+    mesh: &'a Mesh,
+    first: usize,
+    count: usize,
+    instances: usize,
+}
+
+impl<'a> InstancedDraw<'a> {
+    pub fn new(mesh: &'a Mesh) -> Self {
+        InstancedDraw {
+            mesh,
+            first: 0,
+            count: mesh.nelems,
+            instances: mesh.ninstances,
+        }
+    }
+
+    /// Draws `count` indices from index `first`.
+    pub fn range(mut self, first: usize, count: usize) -> Self {
+        self.first = first;
+        self.count = count;
+        self
+    }
+
+    /// Draws the first `n` instances only.
+    pub fn instances(mut self, n: usize) -> Self {
+        self.instances = n;
+        self
+    }
+
+    /// Issues the draw. The mesh must be bound, as by `Program::draw_instanced`.
+    pub fn draw(&self) {
+        let mesh = self.mesh;
+        assert!(
+            self.first + self.count <= mesh.nelems,
+            "draw range out of range"
+        );
+        assert!(
+            self.instances <= mesh.ninstances,
+            "more instances than uploaded"
+        );
+        unsafe {
+            gl::DrawElementsInstanced(
+                mesh.primitive.gl_mode(),
+                self.count as GLsizei,
+                mesh.index_type.gl_type(),
+                (self.first * mesh.index_type.size()) as *const GLvoid,
+                self.instances as GLsizei,
+            );
+        }
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        self.mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::layout::check_layout;
+
+    #[test]
+    fn lays_out_instance_matrix_by_column() {
+        assert_eq!(Instance::stride(), 80);
+        assert_eq!(check_layout::<Instance>(), Ok(()));
+        let locations: Vec<_> = Instance::attributes().iter().map(|a| a.location).collect();
+        assert_eq!(locations, vec![7, 8, 9, 10, 11]);
+    }
+}
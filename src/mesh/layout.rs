@@ -0,0 +1,268 @@
+use super::Vertex;
+use gl::types::*;
+
+/// Component type of a vertex attribute.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AttribType {
+    Byte,
+    UnsignedByte,
+    Short,
+    UnsignedShort,
+    Int,
+    UnsignedInt,
+    HalfFloat,
+    Float,
+}
+
+impl AttribType {
+    pub fn gl_type(self) -> GLenum {
+        match self {
+            AttribType::Byte => gl::BYTE,
+            AttribType::UnsignedByte => gl::UNSIGNED_BYTE,
+            AttribType::Short => gl::SHORT,
+            AttribType::UnsignedShort => gl::UNSIGNED_SHORT,
+            AttribType::Int => gl::INT,
+            AttribType::UnsignedInt => gl::UNSIGNED_INT,
+            AttribType::HalfFloat => gl::HALF_FLOAT,
+            AttribType::Float => gl::FLOAT,
+        }
+    }
+
+    /// Size of one component in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            AttribType::Byte | AttribType::UnsignedByte => 1,
+            AttribType::Short | AttribType::UnsignedShort | AttribType::HalfFloat => 2,
+            AttribType::Int | AttribType::UnsignedInt | AttribType::Float => 4,
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        !matches!(self, AttribType::HalfFloat | AttribType::Float)
+    }
+}
+
+/// One attribute of a vertex type.
+///
+/// Integer components that are not `normalized` reach the shader as
+/// integers (`ivec`/`uvec`); everything else reaches it as floats. An entry
+/// with an empty name continues the one before it at the next location,
+/// like the later columns of a matrix.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VertexAttrib {
+    pub name: &'static str,
+    pub location: GLuint,
+    pub kind: AttribType,
+    pub count: usize,
+    pub normalized: bool,
+    /// Byte offset from the start of the vertex.
+    pub offset: usize,
+}
+
+impl VertexAttrib {
+    pub fn size(&self) -> usize {
+        self.kind.size() * self.count
+    }
+
+    /// Points `location` at this attribute in the bound `ARRAY_BUFFER` and
+    /// enables it.
+    ///
+    /// # Safety
+    /// A VAO and the vertex buffer must be bound.
+    pub unsafe fn enable(&self, stride: usize) {
+        let offset = self.offset as *const GLvoid;
+        if self.kind.is_integer() && !self.normalized {
+            gl::VertexAttribIPointer(
+                self.location,
+                self.count as GLint,
+                self.kind.gl_type(),
+                stride as GLsizei,
+                offset,
+            );
+        } else {
+            gl::VertexAttribPointer(
+                self.location,
+                self.count as GLint,
+                self.kind.gl_type(),
+                self.normalized as GLboolean,
+                stride as GLsizei,
+                offset,
+            );
+        }
+        gl::EnableVertexAttribArray(self.location);
+    }
+}
+
+/// Describes the attributes of a `#[repr(C)]` vertex type so it can be
+/// uploaded to a `Mesh` and bound by name in a `Program`.
+///
+/// `stride()` must equal `size_of::<Self>()`, as the default does; uploads
+/// panic otherwise rather than let GL read past the slice.
+pub trait VertexLayout: Sized {
+    fn attributes() -> &'static [VertexAttrib];
+
+    fn stride() -> usize {
+        std::mem::size_of::<Self>()
+    }
+}
+
+/// Checks that every attribute lies within the vertex, that none overlap
+/// and that no location or name is used twice.
+pub fn check_layout<V: VertexLayout>() -> Result<(), &'static str> {
+    let attribs = V::attributes();
+    for (i, a) in attribs.iter().enumerate() {
+        if a.count == 0 || a.count > 4 {
+            return Err(a.name);
+        }
+        if a.offset + a.size() > V::stride() {
+            return Err(a.name);
+        }
+        for b in attribs[..i].iter() {
+            let overlaps = a.offset < b.offset + b.size() && b.offset < a.offset + a.size();
+            let same_name = !a.name.is_empty() && a.name == b.name;
+            if overlaps || a.location == b.location || same_name {
+                return Err(a.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The stride to upload `V` with, after checking it matches the size of
+/// `V` and, in debug builds, that its layout is sound.
+pub(crate) fn upload_stride<V: VertexLayout>() -> usize {
+    assert_eq!(
+        V::stride(),
+        std::mem::size_of::<V>(),
+        "vertex stride must match the size of the type"
+    );
+    debug_assert!(check_layout::<V>().is_ok(), "invalid vertex layout");
+    V::stride()
+}
+
+pub(crate) const VERTEX_ATTRIBUTES: [VertexAttrib; 6] = [
+    VertexAttrib {
+        name: "position",
+        location: 0,
+        kind: AttribType::Float,
+        count: 3,
+        normalized: false,
+        offset: 0,
+    },
+    VertexAttrib {
+        name: "normal",
+        location: 1,
+        kind: AttribType::Short,
+        count: 3,
+        normalized: true,
+        offset: 12,
+    },
+    VertexAttrib {
+        name: "uv",
+        location: 2,
+        kind: AttribType::UnsignedShort,
+        count: 2,
+        normalized: true,
+        offset: 18,
+    },
+    VertexAttrib {
+        name: "color",
+        location: 3,
+        kind: AttribType::UnsignedByte,
+        count: 3,
+        normalized: true,
+        offset: 22,
+    },
+    VertexAttrib {
+        name: "boneid",
+        location: 4,
+        kind: AttribType::UnsignedByte,
+        count: 2,
+        normalized: false,
+        offset: 26,
+    },
+    VertexAttrib {
+        name: "boneweight",
+        location: 5,
+        kind: AttribType::UnsignedByte,
+        count: 2,
+        normalized: true,
+        offset: 28,
+    },
+];
+
+impl VertexLayout for Vertex {
+    fn attributes() -> &'static [VertexAttrib] {
+        &VERTEX_ATTRIBUTES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct ColoredPoint {
+        position: [f32; 2],
+        color: [u8; 4],
+    }
+
+    impl VertexLayout for ColoredPoint {
+        fn attributes() -> &'static [VertexAttrib] {
+            &[
+                VertexAttrib {
+                    name: "position",
+                    location: 0,
+                    kind: AttribType::Float,
+                    count: 2,
+                    normalized: false,
+                    offset: 0,
+                },
+                VertexAttrib {
+                    name: "color",
+                    location: 1,
+                    kind: AttribType::UnsignedByte,
+                    count: 4,
+                    normalized: true,
+                    offset: 8,
+                },
+            ]
+        }
+    }
+
+    #[allow(dead_code)]
+    struct Overlapping([f32; 2]);
+
+    impl VertexLayout for Overlapping {
+        fn attributes() -> &'static [VertexAttrib] {
+            &[
+                VertexAttrib {
+                    name: "a",
+                    location: 0,
+                    kind: AttribType::Float,
+                    count: 2,
+                    normalized: false,
+                    offset: 0,
+                },
+                VertexAttrib {
+                    name: "b",
+                    location: 1,
+                    kind: AttribType::Float,
+                    count: 1,
+                    normalized: false,
+                    offset: 4,
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn checks_layouts() {
+        assert_eq!(Vertex::stride(), 32);
+        assert_eq!(check_layout::<Vertex>(), Ok(()));
+        assert_eq!(ColoredPoint::stride(), 12);
+        assert_eq!(check_layout::<ColoredPoint>(), Ok(()));
+        assert_eq!(check_layout::<Overlapping>(), Err("b"));
+    }
+}
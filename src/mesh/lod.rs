@@ -0,0 +1,151 @@
+use super::adjacency::AdjacencyError;
+use super::{Mesh, MeshData};
+use crate::bounds::BoundingSphere;
+use crate::program::Program;
+use nalgebra::Matrix4;
+
+/// Roughly the fraction of the viewport height covered by `sphere`'s
+/// diameter, with `sphere` in model space.
+///
+/// Works for perspective and orthographic projections. Spheres reaching
+/// behind the eye count as filling the screen.
+pub fn projected_size(
+    sphere: &BoundingSphere,
+    model_view: &Matrix4<f32>,
+    projection: &Matrix4<f32>,
+) -> f32 {
+    let view = sphere.transform(model_view);
+    let scale = view.radius * projection[(1, 1)].abs();
+    if projection[(3, 2)] == 0.0 {
+        return scale;
+    }
+    let depth = -view.center.z;
+    if depth <= view.radius {
+        f32::INFINITY
+    } else {
+        scale / depth
+    }
+}
+
+/// The first level whose `min_screen_size` is at most `size`, else the
+/// last one.
+fn select_level(min_sizes: impl Iterator<Item = f32>, size: f32) -> usize {
+    let mut last = 0;
+    for (i, min) in min_sizes.enumerate() {
+        if size >= min {
+            return i;
+        }
+        last = i;
+    }
+    last
+}
+
+/// One detail level of a `LodMesh`, drawn while the mesh covers at least
+/// `min_screen_size` of the viewport height.
+pub struct LodLevel {
+    pub mesh: Mesh,
+    pub min_screen_size: f32,
+}
+
+/// A mesh stored at several levels of detail, most detailed first, that
+/// draws whichever one suits its size on screen.
+pub struct LodMesh {
+    levels: Vec<LodLevel>,
+    bounds: BoundingSphere,
+}
+
+impl LodMesh {
+    /// Levels must be ordered by decreasing `min_screen_size`. `bounds`
+    /// should cover all of them.
+    pub fn new(levels: Vec<LodLevel>, bounds: BoundingSphere) -> Self {
+        assert!(!levels.is_empty(), "a LodMesh needs at least one level");
+        LodMesh { levels, bounds }
+    }
+
+    /// Uploads `data` followed by simplified copies of it, one per
+    /// `(face ratio, min screen size)` pair. `data` itself is drawn down
+    /// to `full_detail_size`.
+    pub fn from_data(
+        data: &MeshData,
+        full_detail_size: f32,
+        lods: &[(f32, f32)],
+    ) -> Result<Self, AdjacencyError> {
+        let nfaces = data.faces.len() as f32;
+        let targets: Vec<usize> = lods
+            .iter()
+            .map(|&(ratio, _)| (nfaces * ratio) as usize)
+            .collect();
+        let chain = data.lod_chain(&targets)?;
+
+        let mut levels = vec![LodLevel {
+            mesh: Mesh::from_data(data),
+            min_screen_size: full_detail_size,
+        }];
+        for (level, &(_, min_screen_size)) in chain.iter().zip(lods.iter()) {
+            levels.push(LodLevel {
+                mesh: Mesh::from_data(level),
+                min_screen_size,
+            });
+        }
+        Ok(Self::new(levels, data.bounding_sphere()))
+    }
+
+    pub fn levels(&self) -> &[LodLevel] {
+        &self.levels
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.bounds
+    }
+
+    pub fn screen_size(&self, model_view: &Matrix4<f32>, projection: &Matrix4<f32>) -> f32 {
+        projected_size(&self.bounds, model_view, projection)
+    }
+
+    /// Index of the level to draw at `screen_size`.
+    pub fn level_for(&self, screen_size: f32) -> usize {
+        select_level(self.levels.iter().map(|l| l.min_screen_size), screen_size)
+    }
+
+    /// Draws the level suited to the current view, returning its index.
+    /// Uniforms such as the matrices are left to the caller.
+    pub fn draw(
+        &self,
+        program: &mut Program,
+        model_view: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+    ) -> usize {
+        let level = self.level_for(self.screen_size(model_view, projection));
+        program.draw(&self.levels[level].mesh);
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, Vector3};
+
+    #[test]
+    fn picks_levels_by_screen_size() {
+        let sphere = BoundingSphere::new(Point3::origin(), 1.0);
+        let projection = Matrix4::new_perspective(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let at = |z: f32| Matrix4::new_translation(&Vector3::new(0.0, 0.0, -z));
+
+        // A 90 degree fov shows 2 units of height at distance 1.
+        assert!((projected_size(&sphere, &at(10.0), &projection) - 0.1).abs() < 1e-5);
+        assert!((projected_size(&sphere, &at(20.0), &projection) - 0.05).abs() < 1e-5);
+        assert_eq!(
+            projected_size(&sphere, &at(0.5), &projection),
+            f32::INFINITY
+        );
+        let ortho = Matrix4::new_orthographic(-4.0, 4.0, -4.0, 4.0, 0.1, 100.0);
+        assert!((projected_size(&sphere, &at(50.0), &ortho) - 0.25).abs() < 1e-5);
+
+        let sizes = [0.5, 0.2, 0.05];
+        assert_eq!(select_level(sizes.iter().cloned(), 1.0), 0);
+        assert_eq!(select_level(sizes.iter().cloned(), 0.3), 1);
+        assert_eq!(select_level(sizes.iter().cloned(), 0.1), 2);
+        assert_eq!(select_level(sizes.iter().cloned(), 0.01), 2);
+    }
+}
@@ -0,0 +1,233 @@
+use super::{Face, Mesh, MeshData};
+
+/// An entry in a mesh's material table, indexed by `Vertex::material`.
+///
+/// `texture` is a name or path for the application to resolve; rockwork
+/// does not load it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub base_color: [f32; 4],
+    pub texture: Option<String>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        Material {
+            name: name.to_string(),
+            base_color: [1.0; 4],
+            texture: None,
+        }
+    }
+}
+
+/// A run of faces sharing one material, drawn with one call.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SubMesh {
+    pub material: u8,
+    pub first_face: usize,
+    pub nfaces: usize,
+}
+
+impl SubMesh {
+    /// The first index and index count to pass to `Mesh::draw_range`.
+    pub fn index_range(&self) -> (usize, usize) {
+        (3 * self.first_face, 3 * self.nfaces)
+    }
+}
+
+impl MeshData {
+    /// Sorts faces by the material of their first vertex, keeping their
+    /// order within each material, and replaces `submeshes` with one range
+    /// per material used.
+    pub fn group_by_material(&mut self) {
+        self.sort_faces_by_material();
+        self.rebuild_edges();
+    }
+
+    /// `group_by_material` without touching the edges.
+    fn sort_faces_by_material(&mut self) {
+        let vertices = &self.vertices;
+        let mut keyed: Vec<(u8, _)> = self
+            .faces
+            .drain(..)
+            .map(|f| (vertices[f.vertex_ids[0] as usize].material(), f))
+            .collect();
+        keyed.sort_by_key(|&(m, _)| m);
+
+        self.submeshes.clear();
+        for (i, (m, _)) in keyed.iter().enumerate() {
+            match self.submeshes.last_mut() {
+                Some(s) if s.material == *m => s.nfaces += 1,
+                _ => self.submeshes.push(SubMesh {
+                    material: *m,
+                    first_face: i,
+                    nfaces: 1,
+                }),
+            }
+        }
+        self.faces = keyed.into_iter().map(|(_, f)| f).collect();
+    }
+
+    /// Moves faces back into the sub-mesh they came from after a pass
+    /// reordered or dropped some, keeping the pass's order within each.
+    /// `origin[i]` is the index face `i` had before the pass.
+    ///
+    /// Ranges keep their order and material and only shrink. Faces that
+    /// were outside every range go last.
+    pub(super) fn regroup_submeshes(&mut self, origin: &[usize]) {
+        if self.submeshes.is_empty() {
+            return;
+        }
+        let outside = self.submeshes.len();
+        let end = self
+            .submeshes
+            .iter()
+            .map(|s| s.first_face + s.nfaces)
+            .max()
+            .unwrap_or(0);
+        let mut owner = vec![outside; end];
+        for (i, s) in self.submeshes.iter().enumerate() {
+            for o in owner[s.first_face..s.first_face + s.nfaces].iter_mut() {
+                *o = i;
+            }
+        }
+
+        let mut keyed: Vec<(usize, Face)> = self
+            .faces
+            .drain(..)
+            .zip(origin.iter())
+            .map(|(f, &old)| (owner.get(old).cloned().unwrap_or(outside), f))
+            .collect();
+        keyed.sort_by_key(|&(s, _)| s);
+
+        let mut counts = vec![0; outside + 1];
+        for &(s, _) in keyed.iter() {
+            counts[s] += 1;
+        }
+        let mut first = 0;
+        for (s, &n) in self.submeshes.iter_mut().zip(counts.iter()) {
+            s.first_face = first;
+            s.nfaces = n;
+            first += n;
+        }
+        self.faces = keyed.into_iter().map(|(_, f)| f).collect();
+    }
+
+    /// The material table entry of `submesh`, if the table has one.
+    pub fn submesh_material(&self, submesh: &SubMesh) -> Option<&Material> {
+        self.materials.get(submesh.material as usize)
+    }
+}
+
+impl Mesh {
+    /// Ranges uploaded with `from_data`; empty for a single-material mesh.
+    pub fn submeshes(&self) -> &[SubMesh] {
+        &self.submeshes
+    }
+
+    /// Overrides the ranges, e.g. after uploading faces by hand.
+    pub fn set_submeshes(&mut self, submeshes: Vec<SubMesh>) {
+        self.submeshes = submeshes;
+    }
+
+    /// Draws the faces of one sub-mesh. The mesh must be bound.
+    pub fn draw_submesh(&self, submesh: usize) {
+        let (first, count) = self.submeshes[submesh].index_range();
+        self.draw_range(first, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Face;
+
+    #[test]
+    fn groups_faces_by_material() {
+        let mut data = MeshData::plane(2.0, 2.0, 2, 2);
+        // Mark the vertices of the second and fourth faces' first corners.
+        for &f in [1, 3].iter() {
+            let v = data.faces[f].vertex_ids()[0] as usize;
+            data.vertices
+                .push(data.vertices[v].clone().with_material(2));
+            let [_, b, c] = data.faces[f].vertex_ids();
+            data.faces[f] = Face::new([data.vertices.len() as u32 - 1, b, c]);
+        }
+        let marked = [data.faces[1].clone(), data.faces[3].clone()];
+        let nfaces = data.faces.len();
+        data.build_edges().unwrap();
+        data.group_by_material();
+
+        assert_eq!(
+            data.submeshes,
+            vec![
+                SubMesh {
+                    material: 0,
+                    first_face: 0,
+                    nfaces: nfaces - 2,
+                },
+                SubMesh {
+                    material: 2,
+                    first_face: nfaces - 2,
+                    nfaces: 2,
+                },
+            ]
+        );
+        assert_eq!(data.faces[nfaces - 2..], marked);
+        assert_eq!(data.submeshes[1].index_range(), (3 * (nfaces - 2), 6));
+        assert!(!data.edges.is_empty());
+
+        // Reordering passes keep the grouping.
+        data.optimize();
+        let total: usize = data.submeshes.iter().map(|s| s.nfaces).sum();
+        assert_eq!(total, data.faces.len());
+        for s in data.submeshes.iter() {
+            for f in data.faces[s.first_face..s.first_face + s.nfaces].iter() {
+                let v = &data.vertices[f.vertex_ids()[0] as usize];
+                assert_eq!(v.material(), s.material);
+            }
+        }
+        assert_eq!(data.submesh_material(&data.submeshes[0]), None);
+
+        // Ranges set by hand are kept, not derived again from materials.
+        let mut data = MeshData::plane(2.0, 2.0, 4, 4);
+        let nfaces = data.faces.len();
+        let corners = |data: &MeshData, s: &SubMesh| {
+            let mut c: Vec<Vec<[u32; 3]>> = data.faces[s.first_face..s.first_face + s.nfaces]
+                .iter()
+                .map(|f| {
+                    let mut c: Vec<[u32; 3]> = f
+                        .vertex_ids()
+                        .iter()
+                        .map(|&v| {
+                            let p = data.vertices[v as usize].position();
+                            [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]
+                        })
+                        .collect();
+                    c.sort();
+                    c
+                })
+                .collect();
+            c.sort();
+            c
+        };
+        data.submeshes = vec![
+            SubMesh {
+                material: 0,
+                first_face: 0,
+                nfaces: 5,
+            },
+            SubMesh {
+                material: 0,
+                first_face: 5,
+                nfaces: nfaces - 5,
+            },
+        ];
+        let before: Vec<_> = data.submeshes.iter().map(|s| corners(&data, s)).collect();
+        data.optimize();
+        let after: Vec<_> = data.submeshes.iter().map(|s| corners(&data, s)).collect();
+        assert_eq!(data.submeshes.len(), 2);
+        assert_eq!(after, before);
+    }
+}
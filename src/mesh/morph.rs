@@ -0,0 +1,326 @@
+use super::layout::{upload_stride, AttribType, VertexAttrib, VertexLayout};
+use super::{fill_buffer, Mesh, MeshData, Vertex};
+use crate::gltf::{GltfChannelValues, GltfInterpolation, GltfScene};
+use crate::program::Program;
+use crate::skeleton::Interpolation;
+use gl::types::*;
+use nalgebra::Vector2;
+
+/// How many targets `glsl/morph.vs` blends at once.
+pub const MAX_GPU_MORPH_TARGETS: usize = 2;
+
+/// Name of the `vec2` uniform holding the weights of the GPU targets.
+pub const MORPH_WEIGHTS_UNIFORM: &str = "morph_weights";
+
+/// One blend shape: per-vertex offsets added to the base mesh, scaled by
+/// the target's weight.
+///
+/// `positions` has one delta per vertex. `normals` is either the same
+/// length or empty when the target leaves normals alone.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct MorphTarget {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+}
+
+impl MorphTarget {
+    /// A target that moves nothing yet.
+    pub fn new(name: &str, nverts: usize) -> Self {
+        MorphTarget {
+            name: name.to_string(),
+            positions: vec![[0.0; 3]; nverts],
+            normals: Vec::new(),
+        }
+    }
+
+    /// Whether the deltas line up with `nverts` vertices.
+    pub fn fits(&self, nverts: usize) -> bool {
+        self.positions.len() == nverts && (self.normals.is_empty() || self.normals.len() == nverts)
+    }
+}
+
+fn add_scaled(a: [f32; 3], d: [f32; 3], w: f32) -> [f32; 3] {
+    [a[0] + d[0] * w, a[1] + d[1] * w, a[2] + d[2] * w]
+}
+
+impl MeshData {
+    pub fn find_morph_target(&self, name: &str) -> Option<usize> {
+        self.morph_targets.iter().position(|t| t.name == name)
+    }
+
+    /// The vertices with every target applied at its weight, for uploading
+    /// to a `Dynamic` mesh with `Mesh::update_vertices`. Missing weights
+    /// count as 0.
+    pub fn morphed_vertices(&self, weights: &[f32]) -> Vec<Vertex> {
+        let mut vertices = self.vertices.clone();
+        let mut moved_normals = false;
+        for (target, &w) in self.morph_targets.iter().zip(weights.iter()) {
+            if w == 0.0 {
+                continue;
+            }
+            for (v, &d) in vertices.iter_mut().zip(target.positions.iter()) {
+                v.position = add_scaled(v.position, d, w);
+            }
+            if !target.normals.is_empty() {
+                moved_normals = true;
+                for (v, &d) in vertices.iter_mut().zip(target.normals.iter()) {
+                    v.set_normal(add_scaled(v.normal(), d, w));
+                }
+            }
+        }
+
+        if moved_normals {
+            for v in vertices.iter_mut() {
+                let n = v.normal();
+                let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                if len > 1e-6 {
+                    v.set_normal([n[0] / len, n[1] / len, n[2] / len]);
+                }
+            }
+        }
+        vertices
+    }
+
+    /// Per-vertex deltas of up to `MAX_GPU_MORPH_TARGETS` targets, for
+    /// `Mesh::upload_morph_deltas`. Unused slots are zero.
+    pub fn morph_vertices(&self, targets: &[usize]) -> Vec<MorphVertex> {
+        assert!(
+            targets.len() <= MAX_GPU_MORPH_TARGETS,
+            "too many morph targets for the GPU"
+        );
+        let mut out = vec![MorphVertex::default(); self.vertices.len()];
+        for (slot, &t) in targets.iter().enumerate() {
+            let target = &self.morph_targets[t];
+            for (m, &d) in out.iter_mut().zip(target.positions.iter()) {
+                m.deltas[2 * slot] = d;
+            }
+            for (m, &d) in out.iter_mut().zip(target.normals.iter()) {
+                m.deltas[2 * slot + 1] = d;
+            }
+        }
+        out
+    }
+}
+
+/// Position and normal deltas of two morph targets, for `glsl/morph.vs`.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct MorphVertex {
+    /// Position then normal delta of each target.
+    pub deltas: [[f32; 3]; 2 * MAX_GPU_MORPH_TARGETS],
+}
+
+const fn delta(name: &'static str, k: usize) -> VertexAttrib {
+    VertexAttrib {
+        name,
+        location: 12 + k as GLuint,
+        kind: AttribType::Float,
+        count: 3,
+        normalized: false,
+        offset: 12 * k,
+    }
+}
+
+const MORPH_ATTRIBUTES: [VertexAttrib; 4] = [
+    delta("morph_position0", 0),
+    delta("morph_normal0", 1),
+    delta("morph_position1", 2),
+    delta("morph_normal1", 3),
+];
+
+impl VertexLayout for MorphVertex {
+    fn attributes() -> &'static [VertexAttrib] {
+        &MORPH_ATTRIBUTES
+    }
+}
+
+impl Mesh {
+    /// Uploads morph deltas to a buffer of their own, one per vertex, so
+    /// the base vertices can stay static.
+    pub fn upload_morph_deltas<M: VertexLayout>(&mut self, deltas: &[M]) {
+        assert_eq!(deltas.len(), self.nverts, "one morph delta per vertex");
+        let stride = upload_stride::<M>();
+        unsafe {
+            if self.morph_vbo == 0 {
+                gl::GenBuffers(1, &mut self.morph_vbo);
+            }
+            gl::BindVertexArray(self.vao);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.morph_vbo);
+            fill_buffer(
+                gl::ARRAY_BUFFER,
+                self.usage,
+                &mut self.morph_capacity,
+                deltas.as_ptr() as *const GLvoid,
+                std::mem::size_of_val(deltas),
+            );
+
+            for attrib in M::attributes() {
+                attrib.enable(stride);
+            }
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+}
+
+/// Sets the weights of the targets passed to `MeshData::morph_vertices`.
+pub fn upload_morph_weights(program: &Program, weights: &[f32]) {
+    let w = |i: usize| weights.get(i).cloned().unwrap_or(0.0);
+    program.set_uniform_vec2(MORPH_WEIGHTS_UNIFORM, &Vector2::new(w(0), w(1)));
+}
+
+/// Keyframed weights for every morph target of a mesh. Each key holds
+/// `ntargets` weights.
+#[derive(Clone, Debug)]
+pub struct MorphWeightTrack {
+    pub times: Vec<f32>,
+    pub weights: Vec<f32>,
+    pub ntargets: usize,
+    pub interpolation: Interpolation,
+}
+
+impl MorphWeightTrack {
+    /// The weight channel of `scene`'s animation that targets `node`, if
+    /// any. Cubic spline keys keep only their values and play linearly.
+    pub fn from_gltf(scene: &GltfScene, animation: usize, node: usize) -> Option<Self> {
+        let channel = scene.animations[animation]
+            .channels
+            .iter()
+            .find(|c| c.node == node && matches!(c.values, GltfChannelValues::MorphWeights(_)))?;
+        let weights = match &channel.values {
+            GltfChannelValues::MorphWeights(w) => w,
+            _ => return None,
+        };
+        let (interpolation, stride, offset) = match channel.interpolation {
+            GltfInterpolation::Step => (Interpolation::Step, 1, 0),
+            GltfInterpolation::Linear => (Interpolation::Linear, 1, 0),
+            GltfInterpolation::CubicSpline => (Interpolation::Linear, 3, 1),
+        };
+        let nkeys = channel.times.len();
+        if nkeys == 0 || weights.len() % (nkeys * stride) != 0 {
+            return None;
+        }
+        let ntargets = weights.len() / (nkeys * stride);
+        let weights = (0..nkeys)
+            .flat_map(|k| {
+                let start = (k * stride + offset) * ntargets;
+                weights[start..start + ntargets].iter().cloned()
+            })
+            .collect();
+        Some(MorphWeightTrack {
+            times: channel.times.clone(),
+            weights,
+            ntargets,
+            interpolation,
+        })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.times.last().cloned().unwrap_or(0.0)
+    }
+
+    fn key(&self, k: usize) -> &[f32] {
+        &self.weights[k * self.ntargets..(k + 1) * self.ntargets]
+    }
+
+    /// Writes the weights at `time` into `out`, wrapping around the track
+    /// if `looping`. Targets beyond `out` are ignored.
+    pub fn sample(&self, time: f32, looping: bool, out: &mut [f32]) {
+        let nkeys = self
+            .times
+            .len()
+            .min(self.weights.len() / self.ntargets.max(1));
+        if nkeys == 0 {
+            return;
+        }
+        let duration = self.duration();
+        let time = if looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time
+        };
+        let next = self.times[..nkeys]
+            .iter()
+            .position(|&t| t > time)
+            .unwrap_or(nkeys);
+
+        let (a, b, t) = if next == 0 {
+            (0, 0, 0.0)
+        } else if next == nkeys {
+            (nkeys - 1, nkeys - 1, 0.0)
+        } else {
+            let prev = next - 1;
+            let span = self.times[next] - self.times[prev];
+            let t = match self.interpolation {
+                Interpolation::Linear if span > 0.0 => (time - self.times[prev]) / span,
+                _ => 0.0,
+            };
+            (prev, next, t)
+        };
+        for ((o, &wa), &wb) in out.iter_mut().zip(self.key(a)).zip(self.key(b)) {
+            *o = wa + (wb - wa) * t;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::layout::check_layout;
+
+    #[test]
+    fn blends_targets_on_the_cpu() {
+        let mut quad = MeshData::quad(2.0, 2.0);
+        let mut raise = MorphTarget::new("raise", 4);
+        raise.positions[0] = [0.0, 0.0, 1.0];
+        let mut tilt = MorphTarget::new("tilt", 4);
+        tilt.positions[0] = [1.0, 0.0, 0.0];
+        tilt.normals = vec![[1.0, 0.0, 0.0]; 4];
+        quad.morph_targets = vec![raise, tilt];
+        assert_eq!(quad.find_morph_target("tilt"), Some(1));
+
+        let base = quad.vertices[0].position();
+        let n = quad.vertices[0].normal();
+        let morphed = quad.morphed_vertices(&[0.5, 1.0]);
+        assert_eq!(
+            morphed[0].position(),
+            [base[0] + 1.0, base[1], base[2] + 0.5]
+        );
+        assert_eq!(morphed[1].position(), quad.vertices[1].position());
+        // The normal turns halfway towards x and stays unit length.
+        let m = morphed[2].normal();
+        let d = 1.0 / 2f32.sqrt();
+        let expected = [d + n[0] * d, n[1] * d, n[2] * d];
+        assert!(m
+            .iter()
+            .zip(expected.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-3));
+
+        let gpu = quad.morph_vertices(&[1]);
+        assert_eq!(gpu[0].deltas[0], [1.0, 0.0, 0.0]);
+        assert_eq!(gpu[0].deltas[1], [1.0, 0.0, 0.0]);
+        assert_eq!(gpu[0].deltas[2], [0.0; 3]);
+        assert_eq!(MorphVertex::stride(), 48);
+        assert_eq!(check_layout::<MorphVertex>(), Ok(()));
+    }
+
+    #[test]
+    fn samples_weight_tracks() {
+        let track = MorphWeightTrack {
+            times: vec![0.0, 1.0, 2.0],
+            weights: vec![0.0, 1.0, 1.0, 0.0, 0.5, 0.5],
+            ntargets: 2,
+            interpolation: Interpolation::Linear,
+        };
+        let mut w = [0.0; 2];
+        track.sample(0.5, false, &mut w);
+        assert_eq!(w, [0.5, 0.5]);
+        track.sample(5.0, false, &mut w);
+        assert_eq!(w, [0.5, 0.5]);
+        track.sample(2.25, true, &mut w);
+        assert_eq!(w, [0.25, 0.75]);
+    }
+}
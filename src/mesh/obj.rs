@@ -0,0 +1,501 @@
+use super::{Face, Material, MeshData, Vertex};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum ObjErrorKind {
+    ReadError(io::Error),
+    BadNumber(String),
+    BadIndex(String),
+    IndexOutOfRange(i64),
+    MissingValues(&'static str),
+    DegenerateFace,
+    TooManyVertices,
+    TooManyMaterials,
+}
+
+/// An error in an .obj or .mtl file, with the line it was found on.
+#[derive(Debug)]
+pub struct ObjError {
+    pub file: String,
+    pub line: usize,
+    pub kind: ObjErrorKind,
+}
+
+impl fmt::Display for ObjErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjErrorKind::ReadError(e) => write!(f, "read error: {}", e),
+            ObjErrorKind::BadNumber(s) => write!(f, "bad number {:?}", s),
+            ObjErrorKind::BadIndex(s) => write!(f, "bad face index {:?}", s),
+            ObjErrorKind::IndexOutOfRange(i) => write!(f, "index {} out of range", i),
+            ObjErrorKind::MissingValues(what) => write!(f, "too few values for {}", what),
+            ObjErrorKind::DegenerateFace => write!(f, "face has fewer than 3 vertices"),
+            ObjErrorKind::TooManyVertices => write!(f, "too many unique vertices for a mesh"),
+            ObjErrorKind::TooManyMaterials => write!(f, "more than 256 materials"),
+        }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.kind)
+    }
+}
+
+impl error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ObjErrorKind::ReadError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A material from an .mtl file. Only what a rockwork `Vertex` can carry is
+/// kept, plus the diffuse map for callers that want to load it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: [f32; 3],
+    pub diffuse_map: Option<String>,
+}
+
+impl ObjMaterial {
+    fn new(name: &str) -> Self {
+        ObjMaterial {
+            name: name.to_string(),
+            diffuse: [1.0; 3],
+            diffuse_map: None,
+        }
+    }
+}
+
+/// Opens the .mtl files named by `mtllib`.
+pub type MtlResolver<'a> = dyn FnMut(&str) -> io::Result<Box<dyn BufRead>> + 'a;
+
+struct LineParser<'a> {
+    file: &'a str,
+    line: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn err(&self, kind: ObjErrorKind) -> ObjError {
+        ObjError {
+            file: self.file.to_string(),
+            line: self.line,
+            kind,
+        }
+    }
+
+    fn floats(&self, args: &[&str], what: &'static str, min: usize) -> Result<Vec<f32>, ObjError> {
+        if args.len() < min {
+            return Err(self.err(ObjErrorKind::MissingValues(what)));
+        }
+        args.iter()
+            .map(|a| {
+                a.parse::<f32>()
+                    .map_err(|_| self.err(ObjErrorKind::BadNumber(a.to_string())))
+            })
+            .collect()
+    }
+
+    /// Resolves a 1-based (or negative, relative) index into `len` elements.
+    fn index(&self, s: &str, len: usize) -> Result<usize, ObjError> {
+        let i: i64 = s
+            .parse()
+            .map_err(|_| self.err(ObjErrorKind::BadIndex(s.to_string())))?;
+        let resolved = if i < 0 { len as i64 + i } else { i - 1 };
+        if i == 0 || resolved < 0 || resolved >= len as i64 {
+            return Err(self.err(ObjErrorKind::IndexOutOfRange(i)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+fn for_each_line(
+    f: &mut dyn BufRead,
+    file: &str,
+    mut each: impl FnMut(&LineParser, &str, &[&str]) -> Result<(), ObjError>,
+) -> Result<(), ObjError> {
+    let mut p = LineParser { file, line: 0 };
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        p.line += 1;
+        let n = f
+            .read_line(&mut buf)
+            .map_err(|e| p.err(ObjErrorKind::ReadError(e)))?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        let content = buf.split('#').next().unwrap_or("");
+        let mut words = content.split_whitespace();
+        if let Some(keyword) = words.next() {
+            let args: Vec<&str> = words.collect();
+            each(&p, keyword, &args)?;
+        }
+    }
+}
+
+/// Parses an .mtl file. Unknown statements are ignored.
+pub fn parse_mtl(f: &mut dyn BufRead, file: &str) -> Result<Vec<ObjMaterial>, ObjError> {
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for_each_line(f, file, |p, keyword, args| {
+        match (keyword, materials.last_mut()) {
+            ("newmtl", _) => materials.push(ObjMaterial::new(&args.join(" "))),
+            ("Kd", Some(m)) => {
+                let kd = p.floats(args, "Kd", 3)?;
+                m.diffuse = [kd[0], kd[1], kd[2]];
+            }
+            ("map_Kd", Some(m)) => {
+                // Options such as `-s 1 1 1` come before the file name.
+                m.diffuse_map = args.last().map(|s| s.to_string());
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    Ok(materials)
+}
+
+impl MeshData {
+    /// Loads an .obj without resolving `mtllib`; each `usemtl` still gets its
+    /// own material index, in order of first use.
+    pub fn from_obj(f: &mut dyn BufRead) -> Result<Self, ObjError> {
+        Self::from_obj_with_mtl(f, "obj", &mut |name| {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no resolver for {}", name),
+            ))
+        })
+    }
+
+    /// Loads an .obj from disk, reading material libraries relative to it.
+    pub fn open_obj(path: &Path) -> Result<Self, ObjError> {
+        let name = path.display().to_string();
+        let file = File::open(path).map_err(|e| ObjError {
+            file: name.clone(),
+            line: 0,
+            kind: ObjErrorKind::ReadError(e),
+        })?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        Self::from_obj_with_mtl(&mut BufReader::new(file), &name, &mut |mtl| {
+            Ok(Box::new(BufReader::new(File::open(dir.join(mtl))?)))
+        })
+    }
+
+    /// Loads an .obj, fetching each `mtllib` through `resolve`.
+    ///
+    /// Vertices sharing position, uv, normal and material are merged,
+    /// polygons are fan-triangulated, and the winding is reversed from the
+    /// .obj counter-clockwise convention to the clockwise fronts rockwork
+    /// draws. The diffuse color of a face's material becomes its vertex color.
+    /// Texture coordinates are clamped to [0, 1] by `Vertex::new`. A
+    /// material library that cannot be opened is skipped.
+    ///
+    /// Materials in use fill the material table, keeping their diffuse map
+    /// as the texture, and faces are grouped into one sub-mesh per material.
+    /// Faces before the first `usemtl` get an unnamed, untextured white
+    /// material of their own.
+    pub fn from_obj_with_mtl(
+        f: &mut dyn BufRead,
+        file: &str,
+        resolve: &mut MtlResolver,
+    ) -> Result<Self, ObjError> {
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut colors: Vec<[f32; 3]> = Vec::new();
+        let mut uvs: Vec<[f32; 2]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+
+        let mut library: Vec<ObjMaterial> = Vec::new();
+        let mut used: Vec<ObjMaterial> = Vec::new();
+        let mut material: Option<usize> = None;
+        // Index of the material for faces before any `usemtl`, if there are
+        // such faces.
+        let mut default: Option<usize> = None;
+
+        let mut data = MeshData::default();
+        let mut unique: HashMap<(usize, Option<usize>, Option<usize>, usize), u32> = HashMap::new();
+        let mut corners: Vec<u32> = Vec::new();
+
+        for_each_line(f, file, |p, keyword, args| {
+            match keyword {
+                "v" => {
+                    let v = p.floats(args, "v", 3)?;
+                    positions.push([v[0], v[1], v[2]]);
+                    // Some exporters append a vertex color after xyz.
+                    colors.push(if v.len() >= 6 {
+                        [v[3], v[4], v[5]]
+                    } else {
+                        [1.0; 3]
+                    });
+                }
+                "vt" => {
+                    let t = p.floats(args, "vt", 1)?;
+                    uvs.push([t[0], *t.get(1).unwrap_or(&0.0)]);
+                }
+                "vn" => {
+                    let n = p.floats(args, "vn", 3)?;
+                    normals.push([n[0], n[1], n[2]]);
+                }
+                "o" if data.name.is_empty() => data.name = args.join(" "),
+                "mtllib" => {
+                    for name in args {
+                        if let Ok(mut mtl) = resolve(name) {
+                            library.extend(parse_mtl(&mut mtl, name)?);
+                        }
+                    }
+                }
+                "usemtl" => {
+                    let name = args.join(" ");
+                    let named = used
+                        .iter()
+                        .enumerate()
+                        .position(|(i, m)| Some(i) != default && m.name == name);
+                    material = Some(match named {
+                        Some(i) => i,
+                        None => {
+                            if used.len() > u8::MAX as usize {
+                                return Err(p.err(ObjErrorKind::TooManyMaterials));
+                            }
+                            used.push(
+                                library
+                                    .iter()
+                                    .find(|m| m.name == name)
+                                    .cloned()
+                                    .unwrap_or_else(|| ObjMaterial::new(&name)),
+                            );
+                            used.len() - 1
+                        }
+                    });
+                }
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(p.err(ObjErrorKind::DegenerateFace));
+                    }
+                    let material = match material {
+                        Some(m) => m,
+                        None => *default.get_or_insert_with(|| {
+                            used.push(ObjMaterial::new(""));
+                            used.len() - 1
+                        }),
+                    };
+                    corners.clear();
+                    for corner in args {
+                        let mut parts = corner.split('/');
+                        let v = p.index(parts.next().unwrap_or(""), positions.len())?;
+                        let t = match parts.next() {
+                            Some(s) if !s.is_empty() => Some(p.index(s, uvs.len())?),
+                            _ => None,
+                        };
+                        let n = match parts.next() {
+                            Some(s) if !s.is_empty() => Some(p.index(s, normals.len())?),
+                            _ => None,
+                        };
+
+                        let key = (v, t, n, material);
+                        let id = match unique.get(&key) {
+                            Some(&id) => id,
+                            None => {
+                                if data.vertices.len() >= u32::MAX as usize {
+                                    return Err(p.err(ObjErrorKind::TooManyVertices));
+                                }
+                                let mut color = colors[v];
+                                if let Some(m) = used.get(material) {
+                                    for (c, d) in color.iter_mut().zip(m.diffuse.iter()) {
+                                        *c *= d;
+                                    }
+                                }
+                                data.vertices.push(
+                                    Vertex::new(
+                                        positions[v],
+                                        n.map_or([0.0; 3], |n| normals[n]),
+                                        t.map_or([0.0; 2], |t| uvs[t]),
+                                        color,
+                                    )
+                                    .with_material(material as u8),
+                                );
+                                let id = (data.vertices.len() - 1) as u32;
+                                unique.insert(key, id);
+                                id
+                            }
+                        };
+                        corners.push(id);
+                    }
+
+                    for k in 1..corners.len() - 1 {
+                        data.faces
+                            .push(Face::new([corners[0], corners[k + 1], corners[k]]));
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        // Without any `usemtl` the whole mesh is drawn at once.
+        if used.len() > usize::from(default.is_some()) {
+            // The diffuse color is already in the vertex colors.
+            data.materials = used
+                .iter()
+                .map(|m| Material {
+                    texture: m.diffuse_map.clone(),
+                    ..Material::new(&m.name)
+                })
+                .collect();
+            data.group_by_material();
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const QUAD_OBJ: &str = "# a quad
+mtllib quad.mtl
+o Quad
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/4/1
+usemtl blue
+f -4/-4/-1 -2/-2/-1 -1/-1/-1
+";
+
+    const QUAD_MTL: &str = "newmtl red
+Kd 1 0 0
+map_Kd -s 1 1 1 red.png
+newmtl blue
+Kd 0 0 1
+";
+
+    fn load(obj: &str) -> Result<MeshData, ObjError> {
+        MeshData::from_obj_with_mtl(&mut Cursor::new(obj), "test.obj", &mut |name| {
+            assert_eq!(name, "quad.mtl");
+            Ok(Box::new(Cursor::new(QUAD_MTL)))
+        })
+    }
+
+    #[test]
+    fn loads_quad() {
+        let data = load(QUAD_OBJ).unwrap();
+        assert_eq!(data.name, "Quad");
+        // Four red corners, plus three blue ones that cannot be shared.
+        assert_eq!(data.vertices.len(), 7);
+        assert_eq!(
+            data.faces,
+            vec![
+                Face::new([0, 2, 1]),
+                Face::new([0, 3, 2]),
+                Face::new([4, 6, 5])
+            ]
+        );
+
+        let v = &data.vertices[2];
+        assert_eq!(v.position(), [1.0, 1.0, 0.0]);
+        assert_eq!(v.uv(), [1.0, 1.0]);
+        assert_eq!(v.normal(), [0.0, 0.0, 1.0]);
+        assert_eq!(v.color(), [1.0, 0.0, 0.0]);
+        assert_eq!(v.material(), 0);
+        assert_eq!(data.vertices[4].color(), [0.0, 0.0, 1.0]);
+        assert_eq!(data.vertices[4].material(), 1);
+
+        assert_eq!(data.materials[0].texture, Some("red.png".to_string()));
+        assert_eq!(data.materials[1].name, "blue");
+        let ranges: Vec<_> = data
+            .submeshes
+            .iter()
+            .map(|s| (s.material, s.first_face, s.nfaces))
+            .collect();
+        assert_eq!(ranges, vec![(0, 0, 2), (1, 2, 1)]);
+    }
+
+    #[test]
+    fn keeps_faces_before_usemtl_apart() {
+        let obj = QUAD_OBJ.replace("usemtl red\n", "").replace(
+            "usemtl blue\n",
+            "usemtl red\nf 1/1/1 3/3/1 4/4/1\nusemtl blue\n",
+        );
+        let data = load(&obj).unwrap();
+        let names: Vec<_> = data.materials.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["", "red", "blue"]);
+        assert_eq!(data.materials[0].texture, None);
+        assert_eq!(data.vertices[0].color(), [1.0, 1.0, 1.0]);
+        let ranges: Vec<_> = data
+            .submeshes
+            .iter()
+            .map(|s| (s.material, s.first_face, s.nfaces))
+            .collect();
+        assert_eq!(ranges, vec![(0, 0, 2), (1, 2, 1), (2, 3, 1)]);
+
+        // Without any usemtl there is no table to fill.
+        let plain = load(&QUAD_OBJ.replace("usemtl", "# usemtl")).unwrap();
+        assert!(plain.materials.is_empty());
+        assert!(plain.submeshes.is_empty());
+    }
+
+    #[test]
+    fn winding_is_clockwise_from_the_front() {
+        let data = load(QUAD_OBJ).unwrap();
+        for f in data.faces.iter() {
+            let [a, b, c] = f.vertex_ids();
+            let p = |i: u32| data.vertices[i as usize].position();
+            let (a, b, c) = (p(a), p(b), p(c));
+            let z = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+            // The normal is +z, so the front is seen looking down -z.
+            assert!(z < 0.0);
+        }
+    }
+
+    #[test]
+    fn parses_mtl() {
+        let mats = parse_mtl(&mut Cursor::new(QUAD_MTL), "quad.mtl").unwrap();
+        assert_eq!(mats.len(), 2);
+        assert_eq!(mats[0].diffuse, [1.0, 0.0, 0.0]);
+        assert_eq!(mats[0].diffuse_map, Some("red.png".to_string()));
+        assert_eq!(mats[1].name, "blue");
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let cases = [
+            ("v 0 0 0\nv 1 x 0\n", 2),
+            ("v 0 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 4\n", 4),
+            ("v 0 0 0\nv 1 1 0\n\nf 1 2\n", 4),
+            ("v 0 0 0\nvt 1\nf 1/1/1 1 1\n", 3),
+            ("v 0 0\n", 1),
+        ];
+        for &(obj, line) in cases.iter() {
+            match MeshData::from_obj(&mut Cursor::new(obj)) {
+                Err(e) => {
+                    assert_eq!(e.line, line, "{}", e);
+                    assert_eq!(e.file, "obj");
+                }
+                Ok(_) => panic!("{:?} should not parse", obj),
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_missing_material_library() {
+        let data = MeshData::from_obj(&mut Cursor::new(QUAD_OBJ)).unwrap();
+        assert_eq!(data.faces.len(), 3);
+        assert_eq!(data.vertices[0].color(), [1.0, 1.0, 1.0]);
+        assert_eq!(data.vertices[4].material(), 1);
+    }
+}
@@ -0,0 +1,571 @@
+use super::{face_indices, Face, MeshData, Vertex};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// Size of the LRU cache `optimize_vertex_cache` targets, and of the FIFO
+/// cache `optimize` reports statistics for.
+pub const VERTEX_CACHE_SIZE: usize = 32;
+
+/// How far `optimize` lets overdraw sorting raise the ACMR of each part of
+/// the mesh.
+pub const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// Post-transform vertex cache efficiency of a face order.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// Average cache misses per triangle: 3 at worst, about 0.5 at best.
+    pub acmr: f32,
+    /// Average times each vertex is transformed: 1 at best.
+    pub atvr: f32,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ACMR {:.3}, ATVR {:.3}", self.acmr, self.atvr)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct OptimizeStats {
+    pub before: CacheStats,
+    pub after: CacheStats,
+}
+
+impl fmt::Display for OptimizeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.before, self.after)
+    }
+}
+
+/// A post-transform cache that evicts the oldest vertex first.
+struct FifoCache {
+    vertices: VecDeque<u32>,
+    size: usize,
+}
+
+impl FifoCache {
+    fn new(size: usize) -> Self {
+        FifoCache {
+            vertices: VecDeque::with_capacity(size + 1),
+            size,
+        }
+    }
+
+    /// Uses `v`, returning whether it had to be transformed.
+    fn miss(&mut self, v: u32) -> bool {
+        if self.vertices.contains(&v) {
+            return false;
+        }
+        self.vertices.push_back(v);
+        if self.vertices.len() > self.size {
+            self.vertices.pop_front();
+        }
+        true
+    }
+}
+
+// Scoring from Tom Forsyth's "Linear-Speed Vertex Cache Optimisation".
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+fn vertex_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache = match cache_position {
+        Some(p) if p < 3 => LAST_TRIANGLE_SCORE,
+        Some(p) => {
+            let scale = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (p - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+    cache + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Whether two vertices differ only in where they are.
+fn same_attributes(a: &Vertex, b: &Vertex) -> bool {
+    a.normal == b.normal
+        && a.uv == b.uv
+        && a.color == b.color
+        && a.material == b.material
+        && a.boneid == b.boneid
+        && a.boneweight == b.boneweight
+}
+
+impl MeshData {
+    /// Cache statistics of the current face order for a FIFO cache of
+    /// `cache_size` vertices.
+    pub fn cache_stats(&self, cache_size: usize) -> CacheStats {
+        let mut cache = FifoCache::new(cache_size);
+        let misses = face_indices(&self.faces)
+            .into_iter()
+            .filter(|&v| cache.miss(v))
+            .count() as f32;
+        CacheStats {
+            acmr: misses / self.faces.len().max(1) as f32,
+            atvr: misses / self.vertices.len().max(1) as f32,
+        }
+    }
+
+    /// Runs `optimize_vertex_cache`, `optimize_overdraw` and
+    /// `optimize_vertex_fetch`, reporting the cache statistics before and
+    /// after.
+    pub fn optimize(&mut self) -> OptimizeStats {
+        let before = self.cache_stats(VERTEX_CACHE_SIZE);
+        self.optimize_vertex_cache();
+        self.optimize_overdraw(OVERDRAW_THRESHOLD);
+        self.optimize_vertex_fetch();
+        OptimizeStats {
+            before,
+            after: self.cache_stats(VERTEX_CACHE_SIZE),
+        }
+    }
+
+    /// Merges vertices closer than `tolerance` whose other attributes and
+    /// morph deltas match exactly, and drops faces that collapse. Returns
+    /// how many vertices were removed.
+    ///
+    /// A tolerance of 0 only merges vertices at exactly the same position.
+    pub fn weld_vertices(&mut self, tolerance: f32) -> usize {
+        // With no tolerance, each position gets a cell of its own, keyed by
+        // its bits; adding 0.0 turns -0.0 into 0.0.
+        let exact = tolerance <= 0.0;
+        let reach = if exact { 0 } else { 1 };
+        let cell = |p: [f32; 3]| {
+            let key = |x: f32| {
+                if exact {
+                    (x + 0.0).to_bits() as i64
+                } else {
+                    (x / tolerance).floor() as i64
+                }
+            };
+            [key(p[0]), key(p[1]), key(p[2])]
+        };
+        let same_deltas = |a: usize, b: usize| {
+            self.morph_targets.iter().all(|t| {
+                t.positions.get(a) == t.positions.get(b) && t.normals.get(a) == t.normals.get(b)
+            })
+        };
+
+        let mut grid: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut remap = Vec::with_capacity(self.vertices.len());
+        let mut kept: Vec<usize> = Vec::new();
+        for (i, v) in self.vertices.iter().enumerate() {
+            let c = cell(v.position);
+            let mut found = None;
+            'search: for dx in -reach..=reach {
+                for dy in -reach..=reach {
+                    for dz in -reach..=reach {
+                        // Huge or infinite positions saturate the keys.
+                        let key = [
+                            c[0].saturating_add(dx),
+                            c[1].saturating_add(dy),
+                            c[2].saturating_add(dz),
+                        ];
+                        for &k in grid.get(&key).into_iter().flatten() {
+                            let w = &self.vertices[kept[k as usize]];
+                            let d = sub(v.position, w.position);
+                            if d[0] * d[0] + d[1] * d[1] + d[2] * d[2] <= tolerance * tolerance
+                                && same_attributes(v, w)
+                                && same_deltas(i, kept[k as usize])
+                            {
+                                found = Some(k);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            let id = found.unwrap_or_else(|| {
+                let k = kept.len() as u32;
+                kept.push(i);
+                grid.entry(c).or_default().push(k);
+                k
+            });
+            remap.push(id);
+        }
+
+        let removed = self.vertices.len() - kept.len();
+        let (origin, faces): (Vec<usize>, Vec<Face>) = self
+            .faces
+            .iter()
+            .map(|f| {
+                let [a, b, c] = f.vertex_ids;
+                Face::new([remap[a as usize], remap[b as usize], remap[c as usize]])
+            })
+            .enumerate()
+            .filter(|(_, f)| {
+                let [a, b, c] = f.vertex_ids;
+                a != b && b != c && c != a
+            })
+            .unzip();
+        self.faces = faces;
+        self.regroup_submeshes(&origin);
+        self.keep_vertices(&kept);
+        removed
+    }
+
+    /// Reorders faces so vertices are reused while still in the
+    /// post-transform cache, using Forsyth's algorithm. Vertex ids and the
+    /// winding of each face are unchanged.
+    pub fn optimize_vertex_cache(&mut self) {
+        let nverts = self.vertices.len();
+        let nfaces = self.faces.len();
+        if nfaces == 0 {
+            return;
+        }
+
+        // Faces around each vertex, packed; `remaining` of each list are
+        // still to be emitted.
+        let mut offsets = vec![0; nverts + 1];
+        for f in self.faces.iter() {
+            for &v in f.vertex_ids.iter() {
+                offsets[v as usize + 1] += 1;
+            }
+        }
+        for i in 0..nverts {
+            offsets[i + 1] += offsets[i];
+        }
+        let mut remaining: Vec<usize> = (0..nverts).map(|v| offsets[v + 1] - offsets[v]).collect();
+        let mut adjacent = vec![0; offsets[nverts]];
+        let mut fill = offsets.clone();
+        for (i, f) in self.faces.iter().enumerate() {
+            for &v in f.vertex_ids.iter() {
+                adjacent[fill[v as usize]] = i;
+                fill[v as usize] += 1;
+            }
+        }
+
+        let mut cache_position: Vec<Option<usize>> = vec![None; nverts];
+        let mut vscore: Vec<f32> = (0..nverts)
+            .map(|v| vertex_score(None, remaining[v]))
+            .collect();
+        let tscore_of = |f: &Face, vscore: &[f32]| -> f32 {
+            f.vertex_ids.iter().map(|&v| vscore[v as usize]).sum()
+        };
+        let mut tscore: Vec<f32> = self.faces.iter().map(|f| tscore_of(f, &vscore)).collect();
+        let mut emitted = vec![false; nfaces];
+
+        let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+        let mut order = Vec::with_capacity(nfaces);
+        let mut cursor = 0;
+        let mut best = (0..nfaces).max_by(|&a, &b| tscore[a].partial_cmp(&tscore[b]).unwrap());
+
+        while let Some(face) = best {
+            emitted[face] = true;
+            order.push(face);
+            let ids = self.faces[face].vertex_ids;
+
+            for &v in ids.iter() {
+                let v = v as usize;
+                let list = &mut adjacent[offsets[v]..offsets[v] + remaining[v]];
+                if let Some(k) = list.iter().position(|&f| f == face) {
+                    list.swap(k, remaining[v] - 1);
+                    remaining[v] -= 1;
+                }
+            }
+
+            let mut next_cache: Vec<u32> = ids.to_vec();
+            next_cache.extend(cache.iter().filter(|v| !ids.contains(v)));
+            for &v in next_cache.iter().skip(VERTEX_CACHE_SIZE) {
+                cache_position[v as usize] = None;
+            }
+            let touched = next_cache.clone();
+            next_cache.truncate(VERTEX_CACHE_SIZE);
+            cache = next_cache;
+            for (p, &v) in cache.iter().enumerate() {
+                cache_position[v as usize] = Some(p);
+            }
+
+            for &v in touched.iter() {
+                let v = v as usize;
+                vscore[v] = vertex_score(cache_position[v], remaining[v]);
+            }
+            best = None;
+            let mut best_score = -1.0;
+            for &v in touched.iter() {
+                let v = v as usize;
+                for &f in adjacent[offsets[v]..offsets[v] + remaining[v]].iter() {
+                    tscore[f] = tscore_of(&self.faces[f], &vscore);
+                    if cache_position[v].is_some() && tscore[f] > best_score {
+                        best_score = tscore[f];
+                        best = Some(f);
+                    }
+                }
+            }
+
+            // Nothing left near the cache; start again from the next face
+            // not yet emitted.
+            if best.is_none() {
+                while cursor < nfaces && emitted[cursor] {
+                    cursor += 1;
+                }
+                if cursor < nfaces {
+                    best = Some(cursor);
+                }
+            }
+        }
+
+        self.faces = order.iter().map(|&f| self.faces[f].clone()).collect();
+        self.regroup_submeshes(&order);
+        self.rebuild_edges();
+    }
+
+    /// Sorts clusters of faces so that those facing out from the middle of
+    /// the mesh are drawn first, hiding more of what is drawn after them.
+    ///
+    /// Run after `optimize_vertex_cache`: faces are only split where the
+    /// cache restarts anyway or where doing so keeps each cluster's ACMR
+    /// within `threshold` times what it was, like 1.05 for at most 5% worse.
+    pub fn optimize_overdraw(&mut self, threshold: f32) {
+        let nfaces = self.faces.len();
+        if nfaces == 0 {
+            return;
+        }
+
+        // Hard boundaries, where a face misses the cache entirely.
+        let mut cache = FifoCache::new(VERTEX_CACHE_SIZE);
+        let misses_per_face: Vec<usize> = self
+            .faces
+            .iter()
+            .map(|f| f.vertex_ids.iter().filter(|&&v| cache.miss(v)).count())
+            .collect();
+        let mut hard: Vec<usize> = (0..nfaces).filter(|&f| misses_per_face[f] == 3).collect();
+        if hard.first() != Some(&0) {
+            hard.insert(0, 0);
+        }
+        hard.push(nfaces);
+
+        // Soft boundaries inside each, wherever a cluster drawn from a cold
+        // cache already matches the whole part's ACMR closely enough.
+        let mut starts = Vec::new();
+        for part in hard.windows(2) {
+            let (first, end) = (part[0], part[1]);
+            let part_misses: usize = misses_per_face[first..end].iter().sum();
+            let part_acmr = part_misses as f32 / (end - first) as f32;
+            starts.push(first);
+            let mut start = first;
+            let mut cache = FifoCache::new(VERTEX_CACHE_SIZE);
+            let mut misses = 0;
+            for f in first..end - 1 {
+                let ids = self.faces[f].vertex_ids;
+                misses += ids.iter().filter(|&&v| cache.miss(v)).count();
+                if misses as f32 / (f + 1 - start) as f32 <= part_acmr * threshold {
+                    starts.push(f + 1);
+                    start = f + 1;
+                    cache = FifoCache::new(VERTEX_CACHE_SIZE);
+                    misses = 0;
+                }
+            }
+        }
+        starts.push(nfaces);
+
+        let position = |v: u32| self.vertices[v as usize].position;
+        let mut centre = [0.0; 3];
+        for v in self.vertices.iter() {
+            for (c, p) in centre.iter_mut().zip(v.position.iter()) {
+                *c += p / self.vertices.len() as f32;
+            }
+        }
+
+        // Larger is more outward facing; area weighting comes free with
+        // unnormalized face normals.
+        let mut clusters: Vec<(f32, usize, usize)> = starts
+            .windows(2)
+            .map(|w| {
+                let (mut sum, mut normal, mut area) = ([0.0; 3], [0.0; 3], 0.0);
+                for f in self.faces[w[0]..w[1]].iter() {
+                    let [a, b, c] = f.vertex_ids;
+                    let (pa, pb, pc) = (position(a), position(b), position(c));
+                    let n = cross(sub(pc, pa), sub(pb, pa));
+                    let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                    for k in 0..3 {
+                        normal[k] += n[k];
+                        sum[k] += (pa[k] + pb[k] + pc[k]) / 3.0 * len;
+                    }
+                    area += len;
+                }
+                let len =
+                    (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+                let score = if area > 0.0 && len > 0.0 {
+                    let d = sub([sum[0] / area, sum[1] / area, sum[2] / area], centre);
+                    (d[0] * normal[0] + d[1] * normal[1] + d[2] * normal[2]) / len
+                } else {
+                    0.0
+                };
+                (score, w[0], w[1])
+            })
+            .collect();
+        clusters.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let order: Vec<usize> = clusters
+            .iter()
+            .flat_map(|&(_, first, end)| first..end)
+            .collect();
+        self.faces = order.iter().map(|&f| self.faces[f].clone()).collect();
+        self.regroup_submeshes(&order);
+        self.rebuild_edges();
+    }
+
+    /// Renumbers vertices in the order faces first use them, so vertex
+    /// fetches walk through memory. Vertices no face uses are dropped.
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut kept = Vec::with_capacity(self.vertices.len());
+        for f in self.faces.iter_mut() {
+            for v in f.vertex_ids.iter_mut() {
+                let new = &mut remap[*v as usize];
+                if *new == u32::MAX {
+                    *new = kept.len() as u32;
+                    kept.push(*v as usize);
+                }
+                *v = *new;
+            }
+        }
+        self.keep_vertices(&kept);
+    }
+
+    /// Keeps the vertices and morph deltas at `kept`, in that order, once
+    /// faces have been renumbered to match.
+    pub(super) fn keep_vertices(&mut self, kept: &[usize]) {
+        self.vertices = kept.iter().map(|&i| self.vertices[i].clone()).collect();
+        for target in self.morph_targets.iter_mut() {
+            let remap = |d: &[[f32; 3]]| kept.iter().map(|&i| d[i]).collect();
+            target.positions = remap(&target.positions);
+            if !target.normals.is_empty() {
+                target.normals = remap(&target.normals);
+            }
+        }
+        self.rebuild_edges();
+    }
+
+    /// Edges hold face and vertex ids, so any reordering invalidates them.
+    pub(super) fn rebuild_edges(&mut self) {
+        if !self.edges.is_empty() && self.build_edges().is_err() {
+            self.edges.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each face's corner positions, rotated to a canonical start, sorted.
+    fn triangles(data: &MeshData) -> Vec<[[u32; 3]; 3]> {
+        let mut tris: Vec<_> = data
+            .faces
+            .iter()
+            .map(|f| {
+                let p = |k: usize| {
+                    let p = data.vertices[f.vertex_ids[k] as usize].position;
+                    [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]
+                };
+                let mut corners = [p(0), p(1), p(2)];
+                let start = (0..3).min_by_key(|&k| corners[k]).unwrap();
+                corners.rotate_left(start);
+                corners
+            })
+            .collect();
+        tris.sort();
+        tris
+    }
+
+    #[test]
+    fn improves_cache_efficiency() {
+        let mut grid = MeshData::plane(1.0, 1.0, 40, 40);
+        // Scatter the faces as a careless exporter might.
+        let n = grid.faces.len();
+        grid.faces = (0..n).map(|i| grid.faces[i * 7919 % n].clone()).collect();
+        let original = triangles(&grid);
+
+        let stats = grid.optimize();
+        assert!(stats.before.acmr > 2.0, "{}", stats);
+        assert!(stats.after.acmr < 0.8, "{}", stats);
+        assert_eq!(triangles(&grid), original);
+
+        // Vertices now appear in order of first use.
+        let first_uses: Vec<u32> = face_indices(&grid.faces)
+            .into_iter()
+            .scan(0, |next, v| {
+                let fresh = v == *next;
+                if fresh {
+                    *next += 1;
+                }
+                Some((v, fresh))
+            })
+            .filter(|&(_, fresh)| fresh)
+            .map(|(v, _)| v)
+            .collect();
+        assert_eq!(first_uses.len(), grid.vertices.len());
+    }
+
+    #[test]
+    fn sorts_outward_clusters_first() {
+        let mut sphere = MeshData::icosphere(1.0, 3);
+        sphere.optimize_vertex_cache();
+        let acmr = sphere.cache_stats(VERTEX_CACHE_SIZE).acmr;
+        let original = triangles(&sphere);
+        sphere.optimize_overdraw(OVERDRAW_THRESHOLD);
+        assert_eq!(triangles(&sphere), original);
+        let after = sphere.cache_stats(VERTEX_CACHE_SIZE).acmr;
+        assert!(after <= acmr * 1.25, "{} -> {}", acmr, after);
+
+        // Infinite positions give NaN cluster scores, which still sort.
+        for v in sphere.vertices.iter_mut().step_by(2) {
+            v.position = [f32::INFINITY, f32::NEG_INFINITY, f32::NAN];
+        }
+        sphere.optimize_overdraw(OVERDRAW_THRESHOLD);
+        assert_eq!(sphere.faces.len(), original.len());
+    }
+
+    #[test]
+    fn welds_split_vertices() {
+        let mut plane = MeshData::plane(1.0, 1.0, 4, 4);
+        let nverts = plane.vertices.len();
+        let original = triangles(&plane);
+        plane.compute_flat_normals();
+        assert_eq!(plane.vertices.len(), plane.faces.len() * 3);
+        // Nudged copies still weld; moved ones do not.
+        plane.vertices[0].position[0] += 1e-5;
+        assert_eq!(plane.weld_vertices(1e-4), plane.faces.len() * 3 - nverts);
+        assert_eq!(plane.vertices.len(), nverts);
+        assert_eq!(plane.faces.len(), original.len());
+
+        let mut quad = MeshData::quad(1.0, 1.0);
+        quad.vertices[1].position = quad.vertices[0].position;
+        assert_eq!(quad.weld_vertices(1e-4), 0, "differing uvs never weld");
+        let uv = quad.vertices[0].uv();
+        quad.vertices[1].set_uv(uv);
+        assert_eq!(quad.weld_vertices(1e-4), 1);
+        assert_eq!(quad.faces.len(), 1, "the face that collapsed is gone");
+
+        // No tolerance welds exact copies only, and far or infinite
+        // positions do not overflow the grid.
+        let mut plane = MeshData::plane(1.0, 1.0, 2, 2);
+        let nverts = plane.vertices.len();
+        plane.compute_flat_normals();
+        plane.vertices[0].position[0] += 1e-5;
+        let split = plane.vertices.len();
+        assert_eq!(plane.weld_vertices(0.0), split - nverts - 1);
+        plane.vertices[0].position = [f32::INFINITY, 1e30, -1e30];
+        plane.vertices[1].position = [f32::INFINITY, 1e30, -1e30];
+        plane.weld_vertices(1e-4);
+        plane.weld_vertices(0.0);
+    }
+}
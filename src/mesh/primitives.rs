@@ -0,0 +1,374 @@
+use super::{Face, MeshData, Vertex};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+// Every generator thinks in counter-clockwise triangles, as seen from the
+// side their normals point to, and `push_triangle` flips them into rockwork's
+// clockwise fronts.
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let len = (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt();
+    scale(a, 1.0 / len)
+}
+
+fn push_vertex(data: &mut MeshData, position: [f32; 3], normal: [f32; 3], uv: [f32; 2]) -> u32 {
+    data.vertices
+        .push(Vertex::new(position, normal, uv, [1.0; 3]));
+    (data.vertices.len() - 1) as u32
+}
+
+/// Adds `a b c`, given counter-clockwise from the front. Triangles with two
+/// corners in the same place, as at the poles of a sphere, are dropped.
+fn push_triangle(data: &mut MeshData, a: u32, b: u32, c: u32) {
+    let p = |i: u32| data.vertices[i as usize].position;
+    if p(a) == p(b) || p(b) == p(c) || p(c) == p(a) {
+        return;
+    }
+    data.faces.push(Face::new([a, c, b]));
+}
+
+/// Adds a `cols` by `rows` grid of quads over the unit square. `surface`
+/// maps (u, v) to a position and normal; the derivatives along u and v must
+/// form a right-handed pair with the normal.
+fn push_grid(
+    data: &mut MeshData,
+    cols: usize,
+    rows: usize,
+    surface: impl Fn(f32, f32) -> ([f32; 3], [f32; 3]),
+) {
+    let base = data.vertices.len() as u32;
+    for j in 0..=rows {
+        for i in 0..=cols {
+            let u = i as f32 / cols as f32;
+            let v = j as f32 / rows as f32;
+            let (p, n) = surface(u, v);
+            push_vertex(data, p, n, [u, v]);
+        }
+    }
+
+    let id = |i: usize, j: usize| base + (j * (cols + 1) + i) as u32;
+    for j in 0..rows {
+        for i in 0..cols {
+            push_triangle(data, id(i, j), id(i + 1, j), id(i + 1, j + 1));
+            push_triangle(data, id(i, j), id(i + 1, j + 1), id(i, j + 1));
+        }
+    }
+}
+
+/// Adds a quad centred on `center` spanning `u` and `v` either way, facing
+/// `u` × `v`.
+fn push_rect(data: &mut MeshData, center: [f32; 3], u: [f32; 3], v: [f32; 3], normal: [f32; 3]) {
+    let corner = |su: f32, sv: f32| add(center, add(scale(u, su), scale(v, sv)));
+    let a = push_vertex(data, corner(-1.0, -1.0), normal, [0.0, 0.0]);
+    let b = push_vertex(data, corner(1.0, -1.0), normal, [1.0, 0.0]);
+    let c = push_vertex(data, corner(1.0, 1.0), normal, [1.0, 1.0]);
+    let d = push_vertex(data, corner(-1.0, 1.0), normal, [0.0, 1.0]);
+    push_triangle(data, a, b, c);
+    push_triangle(data, a, c, d);
+}
+
+/// Adds a disc of `radius` at height `y` in the xz plane, facing +y if `up`
+/// and -y otherwise.
+fn push_disc(data: &mut MeshData, radius: f32, y: f32, segments: usize, up: bool) {
+    let normal = [0.0, if up { 1.0 } else { -1.0 }, 0.0];
+    let center = push_vertex(data, [0.0, y, 0.0], normal, [0.5, 0.5]);
+    let ring: Vec<u32> = (0..=segments)
+        .map(|k| {
+            let (s, c) = (2.0 * PI * k as f32 / segments as f32).sin_cos();
+            let uv = [0.5 + 0.5 * c, 0.5 + 0.5 * s];
+            push_vertex(data, [radius * c, y, -radius * s], normal, uv)
+        })
+        .collect();
+    for k in 0..segments {
+        if up {
+            push_triangle(data, center, ring[k], ring[k + 1]);
+        } else {
+            push_triangle(data, center, ring[k + 1], ring[k]);
+        }
+    }
+}
+
+impl MeshData {
+    /// A `width` by `height` rectangle in the xy plane, facing +z.
+    pub fn quad(width: f32, height: f32) -> Self {
+        let mut data = MeshData::new("quad");
+        push_rect(
+            &mut data,
+            [0.0; 3],
+            [width / 2.0, 0.0, 0.0],
+            [0.0, height / 2.0, 0.0],
+            [0.0, 0.0, 1.0],
+        );
+        data
+    }
+
+    /// An axis-aligned cube with edges of length `size`. Each side has its
+    /// own four vertices so normals and uvs stay flat.
+    pub fn cube(size: f32) -> Self {
+        let mut data = MeshData::new("cube");
+        let h = size / 2.0;
+        let sides = [
+            ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ];
+        for &(n, u, v) in sides.iter() {
+            push_rect(&mut data, scale(n, h), scale(u, h), scale(v, h), n);
+        }
+        data
+    }
+
+    /// A `width` by `depth` grid in the xz plane, facing +y, split into
+    /// `cols` by `rows` quads.
+    pub fn plane(width: f32, depth: f32, cols: usize, rows: usize) -> Self {
+        let mut data = MeshData::new("plane");
+        push_grid(&mut data, cols.max(1), rows.max(1), |u, v| {
+            let p = [(u - 0.5) * width, 0.0, (0.5 - v) * depth];
+            (p, [0.0, 1.0, 0.0])
+        });
+        data
+    }
+
+    /// A sphere of `segments` slices around y and `rings` stacks from pole
+    /// to pole. The uvs are an equirectangular mapping.
+    pub fn uv_sphere(radius: f32, segments: usize, rings: usize) -> Self {
+        let mut data = MeshData::new("uv_sphere");
+        push_grid(&mut data, segments.max(3), rings.max(2), |u, v| {
+            let (st, ct) = (2.0 * PI * u).sin_cos();
+            let (mut sa, ca) = (PI * (1.0 - v)).sin_cos();
+            // Pin the poles so their triangles collapse exactly.
+            if v == 0.0 || v == 1.0 {
+                sa = 0.0;
+            }
+            let n = [sa * ct, ca, -sa * st];
+            (scale(n, radius), n)
+        });
+        data
+    }
+
+    /// A sphere made by splitting each triangle of an icosahedron into four
+    /// `subdivisions` times. Vertices are evenly spread, but the uvs have a
+    /// seam that some triangles stretch across.
+    pub fn icosphere(radius: f32, subdivisions: usize) -> Self {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut points: Vec<[f32; 3]> = [
+            [-1.0, t, 0.0],
+            [1.0, t, 0.0],
+            [-1.0, -t, 0.0],
+            [1.0, -t, 0.0],
+            [0.0, -1.0, t],
+            [0.0, 1.0, t],
+            [0.0, -1.0, -t],
+            [0.0, 1.0, -t],
+            [t, 0.0, -1.0],
+            [t, 0.0, 1.0],
+            [-t, 0.0, -1.0],
+            [-t, 0.0, 1.0],
+        ]
+        .iter()
+        .map(|&p| normalize(p))
+        .collect();
+        let mut tris: Vec<[usize; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, points: &mut Vec<[f32; 3]>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    points.push(normalize(add(points[a], points[b])));
+                    points.len() - 1
+                })
+            };
+            let mut next = Vec::with_capacity(tris.len() * 4);
+            for &[a, b, c] in tris.iter() {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                next.push([a, ab, ca]);
+                next.push([b, bc, ab]);
+                next.push([c, ca, bc]);
+                next.push([ab, bc, ca]);
+            }
+            tris = next;
+        }
+
+        let mut data = MeshData::new("icosphere");
+        for &n in points.iter() {
+            let uv = [
+                0.5 + (-n[2]).atan2(n[0]) / (2.0 * PI),
+                0.5 + n[1].asin() / PI,
+            ];
+            push_vertex(&mut data, scale(n, radius), n, uv);
+        }
+        for &[a, b, c] in tris.iter() {
+            push_triangle(&mut data, a as u32, b as u32, c as u32);
+        }
+        data
+    }
+
+    /// A capped cylinder around the y axis, centred on the origin.
+    pub fn cylinder(radius: f32, height: f32, segments: usize) -> Self {
+        let segments = segments.max(3);
+        let mut data = MeshData::new("cylinder");
+        push_grid(&mut data, segments, 1, |u, v| {
+            let (s, c) = (2.0 * PI * u).sin_cos();
+            ([radius * c, (v - 0.5) * height, -radius * s], [c, 0.0, -s])
+        });
+        push_disc(&mut data, radius, height / 2.0, segments, true);
+        push_disc(&mut data, radius, -height / 2.0, segments, false);
+        data
+    }
+
+    /// A capped cone around the y axis, centred on the origin, with its
+    /// tip at +y.
+    pub fn cone(radius: f32, height: f32, segments: usize) -> Self {
+        let segments = segments.max(3);
+        let mut data = MeshData::new("cone");
+        let slant = (radius * radius + height * height).sqrt();
+        push_grid(&mut data, segments, 1, |u, v| {
+            let (s, c) = (2.0 * PI * u).sin_cos();
+            let r = radius * (1.0 - v);
+            let n = [height * c / slant, radius / slant, -height * s / slant];
+            ([r * c, (v - 0.5) * height, -r * s], n)
+        });
+        push_disc(&mut data, radius, -height / 2.0, segments, false);
+        data
+    }
+
+    /// A torus around the y axis: a tube of `minor_radius` swept along a
+    /// circle of `major_radius` in the xz plane.
+    pub fn torus(
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: usize,
+        minor_segments: usize,
+    ) -> Self {
+        let mut data = MeshData::new("torus");
+        push_grid(
+            &mut data,
+            major_segments.max(3),
+            minor_segments.max(3),
+            |u, v| {
+                let (st, ct) = (2.0 * PI * u).sin_cos();
+                let (sp, cp) = (2.0 * PI * v).sin_cos();
+                let n = [cp * ct, sp, -cp * st];
+                let r = major_radius + minor_radius * cp;
+                ([r * ct, minor_radius * sp, -r * st], n)
+            },
+        );
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+        [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+    }
+
+    fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+        [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ]
+    }
+
+    fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    /// Every face must be clockwise seen from the side its vertex normals
+    /// point to, and every normal must be unit length.
+    fn check_winding(data: &MeshData) {
+        assert!(!data.faces.is_empty(), "{} has no faces", data.name);
+        for v in data.vertices.iter() {
+            let len = dot(v.normal(), v.normal()).sqrt();
+            assert!((len - 1.0).abs() < 1e-3, "{} has a bad normal", data.name);
+        }
+        for f in data.faces.iter() {
+            let [a, b, c] = f.vertex_ids();
+            let v = |i: u32| &data.vertices[i as usize];
+            let (pa, pb, pc) = (v(a).position(), v(b).position(), v(c).position());
+            let facing = cross(sub(pc, pa), sub(pb, pa));
+            let normal = add(add(v(a).normal(), v(b).normal()), v(c).normal());
+            assert!(
+                dot(facing, normal) > 0.0,
+                "{} face {:?} is wound the wrong way",
+                data.name,
+                f
+            );
+        }
+    }
+
+    #[test]
+    fn winds_all_primitives_clockwise() {
+        for data in [
+            MeshData::quad(2.0, 1.0),
+            MeshData::cube(1.0),
+            MeshData::plane(4.0, 2.0, 3, 2),
+            MeshData::uv_sphere(1.0, 12, 6),
+            MeshData::icosphere(2.0, 2),
+            MeshData::cylinder(0.5, 2.0, 10),
+            MeshData::cone(0.5, 2.0, 10),
+            MeshData::torus(2.0, 0.5, 12, 8),
+        ]
+        .iter()
+        {
+            check_winding(data);
+        }
+    }
+
+    #[test]
+    fn builds_expected_topology() {
+        let cube = MeshData::cube(2.0);
+        assert_eq!((cube.vertices.len(), cube.faces.len()), (24, 12));
+        assert!(cube
+            .vertices
+            .iter()
+            .all(|v| v.position().iter().all(|c| c.abs() == 1.0)));
+
+        let ico = MeshData::icosphere(1.0, 1);
+        assert_eq!((ico.vertices.len(), ico.faces.len()), (42, 80));
+        let mut ico = ico;
+        ico.build_edges().unwrap();
+        assert!(ico.adjacency().unwrap().boundary_edges().next().is_none());
+
+        // The pole rows lose one triangle per quad.
+        let sphere = MeshData::uv_sphere(1.0, 8, 4);
+        assert_eq!(sphere.faces.len(), 8 * 2 * 4 - 2 * 8);
+    }
+}
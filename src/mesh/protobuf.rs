@@ -0,0 +1,431 @@
+use super::{Edge, Face, MeshData, MorphTarget, Vertex, NO_ID};
+use crate::proto::model;
+use prost::Message;
+use std::io::{Read, Write};
+use std::{error, fmt, io};
+
+/// `type_url` used for the `VertexData` packed into `Vertex.data`.
+pub const VERTEX_DATA_TYPE_URL: &str = "type.googleapis.com/rockwork.model.VertexData";
+
+/// Stands in for `NO_ID` in protobuf edges.
+const PROTO_NO_ID: u32 = u32::MAX;
+
+#[derive(Debug)]
+pub enum ProtoError {
+    ReadError(io::Error),
+    WriteError(io::Error),
+    DecodeError(prost::DecodeError),
+    EncodeError(prost::EncodeError),
+    TooManyVertices(usize),
+    DegenerateFace(usize),
+    VertexOutOfRange { face: usize, vertex: u32 },
+    BadEdge(usize),
+    BadMorphTarget(usize),
+    BadVertexData(usize),
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtoError::ReadError(e) => write!(f, "error reading model: {}", e),
+            ProtoError::WriteError(e) => write!(f, "error writing model: {}", e),
+            ProtoError::DecodeError(e) => write!(f, "error decoding model: {}", e),
+            ProtoError::EncodeError(e) => write!(f, "error encoding model: {}", e),
+            ProtoError::TooManyVertices(n) => {
+                write!(f, "model has {} vertices, more than a mesh can index", n)
+            }
+            ProtoError::DegenerateFace(face) => {
+                write!(f, "model face {} has fewer than 3 vertices", face)
+            }
+            ProtoError::VertexOutOfRange { face, vertex } => {
+                write!(
+                    f,
+                    "model face {} references missing vertex {}",
+                    face, vertex
+                )
+            }
+            ProtoError::BadEdge(e) => write!(f, "model edge {} is malformed", e),
+            ProtoError::BadMorphTarget(t) => write!(
+                f,
+                "model morph target {} does not have one delta per vertex",
+                t
+            ),
+            ProtoError::BadVertexData(v) => {
+                write!(f, "model vertex {} has a bone id or material above 255", v)
+            }
+        }
+    }
+}
+
+impl error::Error for ProtoError {}
+
+fn vec3(v: [f32; 3]) -> Option<model::Vec3> {
+    Some(model::Vec3 {
+        x: v[0],
+        y: v[1],
+        z: v[2],
+    })
+}
+
+fn from_vec3(v: &Option<model::Vec3>) -> [f32; 3] {
+    v.as_ref().map_or([0.0; 3], |v| [v.x, v.y, v.z])
+}
+
+fn to_proto_id(id: u16) -> u32 {
+    if id == NO_ID {
+        PROTO_NO_ID
+    } else {
+        id as u32
+    }
+}
+
+fn from_proto_id(id: u32) -> Option<u16> {
+    if id == PROTO_NO_ID {
+        Some(NO_ID)
+    } else if id < NO_ID as u32 {
+        Some(id as u16)
+    } else {
+        None
+    }
+}
+
+impl Vertex {
+    fn to_proto(&self) -> model::Vertex {
+        let uv = self.uv();
+        let data = model::VertexData {
+            color: vec3(self.color()),
+            material: self.material as u32,
+            bone_ids: self.boneid.iter().map(|&b| b as u32).collect(),
+            bone_weights: self.bone_weights().to_vec(),
+        };
+        let mut value = Vec::with_capacity(data.encoded_len());
+        data.encode(&mut value).unwrap();
+
+        model::Vertex {
+            position: vec3(self.position),
+            normal: vec3(self.normal()),
+            uv: Some(model::Vec2 { x: uv[0], y: uv[1] }),
+            data: Some(prost_types::Any {
+                type_url: VERTEX_DATA_TYPE_URL.to_string(),
+                value,
+            }),
+        }
+    }
+
+    /// `index` is only used to report errors.
+    fn from_proto(index: usize, v: &model::Vertex) -> Result<Self, ProtoError> {
+        let uv = v.uv.as_ref().map_or([0.0; 2], |uv| [uv.x, uv.y]);
+        let mut vert = Vertex::new(from_vec3(&v.position), from_vec3(&v.normal), uv, [1.0; 3]);
+
+        // Data of any other type is left for the application to interpret.
+        if let Some(any) = v
+            .data
+            .as_ref()
+            .filter(|a| a.type_url == VERTEX_DATA_TYPE_URL)
+        {
+            let data =
+                model::VertexData::decode(&any.value[..]).map_err(ProtoError::DecodeError)?;
+            let byte = |n: u32| {
+                if n > u8::MAX as u32 {
+                    Err(ProtoError::BadVertexData(index))
+                } else {
+                    Ok(n as u8)
+                }
+            };
+            let id = |i: usize| byte(*data.bone_ids.get(i).unwrap_or(&0));
+            let weight = |i: usize| *data.bone_weights.get(i).unwrap_or(&0.0);
+            vert = vert
+                .with_material(byte(data.material)?)
+                .with_bones([id(0)?, id(1)?], [weight(0), weight(1)]);
+            vert.set_color(from_vec3(&data.color));
+        }
+        Ok(vert)
+    }
+}
+
+impl MorphTarget {
+    fn to_proto(&self) -> model::MorphTarget {
+        let deltas = |v: &[[f32; 3]]| {
+            v.iter()
+                .map(|d| model::Vec3 {
+                    x: d[0],
+                    y: d[1],
+                    z: d[2],
+                })
+                .collect()
+        };
+        model::MorphTarget {
+            name: self.name.clone(),
+            positions: deltas(&self.positions),
+            normals: deltas(&self.normals),
+        }
+    }
+
+    fn from_proto(t: &model::MorphTarget) -> Self {
+        let deltas = |v: &[model::Vec3]| v.iter().map(|d| [d.x, d.y, d.z]).collect();
+        MorphTarget {
+            name: t.name.clone(),
+            positions: deltas(&t.positions),
+            normals: deltas(&t.normals),
+        }
+    }
+}
+
+impl Edge {
+    fn to_proto(&self) -> model::Edge {
+        let half = |side: usize| model::HalfEdge {
+            vertex_id: self.vertex_ids[side] as u32,
+            next_face_id: to_proto_id(self.face_ids[side]),
+            next_edge_id: to_proto_id(self.second_edges[side]),
+            prev_edge_id: to_proto_id(self.first_edges[side]),
+        };
+        model::Edge {
+            v1: Some(half(0)),
+            v2: Some(half(1)),
+            data: None,
+        }
+    }
+
+    fn from_proto(e: &model::Edge) -> Option<Self> {
+        let mut edge: Edge = Default::default();
+        for (side, half) in [&e.v1, &e.v2].iter().enumerate() {
+            let half = half.as_ref()?;
+            edge.vertex_ids[side] = from_proto_id(half.vertex_id)?;
+            edge.face_ids[side] = from_proto_id(half.next_face_id)?;
+            edge.second_edges[side] = from_proto_id(half.next_edge_id)?;
+            edge.first_edges[side] = from_proto_id(half.prev_edge_id)?;
+        }
+        Some(edge)
+    }
+
+    /// Whether every id refers to something in a mesh of this size, the
+    /// way `Adjacency::new` checks them.
+    fn fits(&self, nverts: usize, nfaces: usize, nedges: usize) -> bool {
+        let in_range = |id: u16, n: usize| id == NO_ID || (id as usize) < n;
+        let [a, b] = self.vertex_ids;
+        (a as usize) < nverts
+            && (b as usize) < nverts
+            && a != b
+            && self.face_ids.iter().all(|&f| in_range(f, nfaces))
+            && self.first_edges.iter().all(|&e| in_range(e, nedges))
+            && self.second_edges.iter().all(|&e| in_range(e, nedges))
+    }
+}
+
+impl MeshData {
+    /// Converts from the protobuf model.
+    ///
+    /// Polygon faces are fan-triangulated. Edges are range-checked and taken
+    /// as-is when every face was already a triangle; otherwise they describe
+    /// polygons that no longer exist, so they are rebuilt from the triangles
+    /// instead (and left empty if the result is not manifold).
+    pub fn from_proto(m: &model::Model) -> Result<Self, ProtoError> {
+        if m.vertices.len() > u32::MAX as usize {
+            return Err(ProtoError::TooManyVertices(m.vertices.len()));
+        }
+
+        let mut data = MeshData::new(&m.name);
+        data.nbones = m.nbones.min(u8::MAX as u32) as u8;
+        data.vertices = m
+            .vertices
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Vertex::from_proto(i, v))
+            .collect::<Result<_, _>>()?;
+
+        let mut triangulated = false;
+        for (i, face) in m.faces.iter().enumerate() {
+            let ids = &face.vertex_ids;
+            if ids.len() < 3 {
+                return Err(ProtoError::DegenerateFace(i));
+            }
+            if let Some(&v) = ids.iter().find(|&&v| v as usize >= m.vertices.len()) {
+                return Err(ProtoError::VertexOutOfRange { face: i, vertex: v });
+            }
+            triangulated |= ids.len() > 3;
+            for k in 1..ids.len() - 1 {
+                data.faces.push(Face::new([ids[0], ids[k], ids[k + 1]]));
+            }
+        }
+
+        if triangulated {
+            if !m.edges.is_empty() && data.build_edges().is_err() {
+                data.edges.clear();
+            }
+        } else {
+            data.edges = m
+                .edges
+                .iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    Edge::from_proto(e)
+                        .filter(|e| e.fits(data.vertices.len(), data.faces.len(), m.edges.len()))
+                        .ok_or(ProtoError::BadEdge(i))
+                })
+                .collect::<Result<_, _>>()?;
+            data.assign_incident_edges();
+        }
+
+        for (i, t) in m.morph_targets.iter().enumerate() {
+            let target = MorphTarget::from_proto(t);
+            if !target.fits(data.vertices.len()) {
+                return Err(ProtoError::BadMorphTarget(i));
+            }
+            data.morph_targets.push(target);
+        }
+        Ok(data)
+    }
+
+    pub fn to_proto(&self) -> model::Model {
+        model::Model {
+            name: self.name.clone(),
+            vertices: self.vertices.iter().map(Vertex::to_proto).collect(),
+            faces: self
+                .faces
+                .iter()
+                .map(|f| model::Face {
+                    vertex_ids: f.vertex_ids.to_vec(),
+                    data: None,
+                })
+                .collect(),
+            edges: self.edges.iter().map(Edge::to_proto).collect(),
+            nbones: self.nbones as u32,
+            morph_targets: self
+                .morph_targets
+                .iter()
+                .map(MorphTarget::to_proto)
+                .collect(),
+        }
+    }
+
+    /// Reads a serialized `rockwork.model.Model`.
+    pub fn from_protobuf(f: &mut dyn Read) -> Result<Self, ProtoError> {
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).map_err(ProtoError::ReadError)?;
+        let m = model::Model::decode(&buf[..]).map_err(ProtoError::DecodeError)?;
+        Self::from_proto(&m)
+    }
+
+    pub fn write_protobuf(&self, w: &mut impl Write) -> Result<(), ProtoError> {
+        let m = self.to_proto();
+        let mut buf = Vec::with_capacity(m.encoded_len());
+        m.encode(&mut buf).map_err(ProtoError::EncodeError)?;
+        w.write_all(&buf).map_err(ProtoError::WriteError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    static UNIT_QUAD: &[u8] = include_bytes!("../../examples/draw/assets/unit_quad.mdl");
+
+    #[test]
+    fn round_trips_through_protobuf() {
+        let mut data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        data.build_edges().unwrap();
+        data.vertices[1] = data.vertices[1]
+            .clone()
+            .with_bones([4, 2], [0.5, 0.5])
+            .with_material(7);
+
+        let mut smile = MorphTarget::new("smile", 4);
+        smile.positions[2] = [0.0, 0.5, 0.0];
+        data.morph_targets.push(smile);
+
+        let mut buf = Vec::new();
+        data.write_protobuf(&mut buf).unwrap();
+        let read = MeshData::from_protobuf(&mut Cursor::new(&buf)).unwrap();
+
+        assert_eq!(read.name, data.name);
+        assert_eq!(read.morph_targets, data.morph_targets);
+        assert_eq!(read.nbones, data.nbones);
+        assert_eq!(read.faces, data.faces);
+        assert_eq!(read.edges, data.edges);
+        assert!(read.adjacency().is_ok());
+        for (a, b) in read.vertices.iter().zip(data.vertices.iter()) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.normal, b.normal);
+            assert_eq!(a.uv, b.uv);
+            assert_eq!(a.color, b.color);
+            assert_eq!(a.material, b.material);
+            assert_eq!(a.boneid, b.boneid);
+            assert_eq!(a.boneweight, b.boneweight);
+        }
+    }
+
+    #[test]
+    fn triangulates_polygons() {
+        let data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        let mut m = data.to_proto();
+        m.faces = vec![model::Face {
+            vertex_ids: vec![0, 1, 2, 3],
+            data: None,
+        }];
+        // Edges for the polygon; they should be replaced.
+        m.edges = vec![Edge::default().to_proto()];
+
+        let read = MeshData::from_proto(&m).unwrap();
+        assert_eq!(read.faces, vec![Face::new([0, 1, 2]), Face::new([0, 2, 3])]);
+        assert_eq!(read.edges.len(), 5);
+        assert!(read.adjacency().is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_faces() {
+        let data = MeshData::from_mdl(&mut Cursor::new(UNIT_QUAD)).unwrap();
+        let mut m = data.to_proto();
+        m.faces[1].vertex_ids = vec![0, 1];
+        match MeshData::from_proto(&m) {
+            Err(ProtoError::DegenerateFace(1)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        m.faces[1].vertex_ids = vec![0, 1, 9];
+        match MeshData::from_proto(&m) {
+            Err(ProtoError::VertexOutOfRange { face: 1, vertex: 9 }) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let mut m = data.to_proto();
+        m.morph_targets = vec![MorphTarget::new("short", 3).to_proto()];
+        match MeshData::from_proto(&m) {
+            Err(ProtoError::BadMorphTarget(0)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let mut with_edges = data.clone();
+        with_edges.build_edges().unwrap();
+        let mut m = with_edges.to_proto();
+        m.edges[2].v1.as_mut().unwrap().vertex_id = 4;
+        match MeshData::from_proto(&m) {
+            Err(ProtoError::BadEdge(2)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        let mut m = with_edges.to_proto();
+        m.edges[0].v2.as_mut().unwrap().next_face_id = 2;
+        match MeshData::from_proto(&m) {
+            Err(ProtoError::BadEdge(0)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let mut m = data.to_proto();
+        let mut vertex = data.vertices[3].to_proto();
+        let any = vertex.data.as_mut().unwrap();
+        let mut extra = model::VertexData::decode(&any.value[..]).unwrap();
+        extra.bone_ids = vec![0, 256];
+        any.value.clear();
+        extra.encode(&mut any.value).unwrap();
+        m.vertices[3] = vertex;
+        match MeshData::from_proto(&m) {
+            Err(ProtoError::BadVertexData(3)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        match MeshData::from_protobuf(&mut Cursor::new(&[0xff, 0xff, 0xff][..])) {
+            Err(ProtoError::DecodeError(_)) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use super::adjacency::AdjacencyError;
+use super::{Face, MeshData, NO_ID};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// How much more moving a mesh's open border costs than moving its surface
+/// by the same distance.
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+type Vec3 = [f64; 3];
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: Vec3) -> Option<Vec3> {
+    let len = dot(a, a).sqrt();
+    if len > 1e-12 {
+        Some([a[0] / len, a[1] / len, a[2] / len])
+    } else {
+        None
+    }
+}
+
+/// Sum of squared distances to a set of weighted planes, as the symmetric
+/// 4x4 matrix `[A b; b c]`: a², ab, ac, ad, b², bc, bd, c², cd, d².
+#[derive(Copy, Clone, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// The plane `n · p + d = 0` with unit `n`.
+    fn plane(n: Vec3, d: f64, weight: f64) -> Self {
+        let [a, b, c] = n;
+        let q = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ];
+        Quadric([
+            q[0] * weight,
+            q[1] * weight,
+            q[2] * weight,
+            q[3] * weight,
+            q[4] * weight,
+            q[5] * weight,
+            q[6] * weight,
+            q[7] * weight,
+            q[8] * weight,
+            q[9] * weight,
+        ])
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        for (s, o) in sum.0.iter_mut().zip(other.0.iter()) {
+            *s += o;
+        }
+        sum
+    }
+
+    fn error(&self, p: Vec3) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = p;
+        x * x * q[0]
+            + 2.0 * x * y * q[1]
+            + 2.0 * x * z * q[2]
+            + 2.0 * x * q[3]
+            + y * y * q[4]
+            + 2.0 * y * z * q[5]
+            + 2.0 * y * q[6]
+            + z * z * q[7]
+            + 2.0 * z * q[8]
+            + q[9]
+    }
+
+    /// The point of least error, unless the planes do not pin one down.
+    fn optimum(&self) -> Option<Vec3> {
+        let q = &self.0;
+        let (a, b, c) = ([q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]);
+        let det = dot(a, cross(b, c));
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        // Cramer's rule for A p = -b.
+        let r = [-q[3], -q[6], -q[8]];
+        Some([
+            dot(r, cross(b, c)) / det,
+            dot(a, cross(r, c)) / det,
+            dot(a, cross(b, r)) / det,
+        ])
+    }
+}
+
+/// Collapsing `remove` into `keep` at `target`, ordered cheapest first.
+struct Collapse {
+    cost: f64,
+    keep: u32,
+    remove: u32,
+    target: Vec3,
+    /// Versions of both vertices when this was scored.
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+struct Simplifier {
+    positions: Vec<Vec3>,
+    quadrics: Vec<Quadric>,
+    locked: Vec<bool>,
+    boundary: Vec<bool>,
+    versions: Vec<u32>,
+    faces: Vec<[u32; 3]>,
+    live: Vec<bool>,
+    /// Faces around each vertex; may include dead ones.
+    vertex_faces: Vec<Vec<usize>>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(data: &MeshData) -> Result<Self, AdjacencyError> {
+        let adjacency = data.adjacency()?;
+        let nverts = data.vertices.len();
+        let positions: Vec<Vec3> = data
+            .vertices
+            .iter()
+            .map(|v| {
+                let p = v.position;
+                [p[0] as f64, p[1] as f64, p[2] as f64]
+            })
+            .collect();
+        let faces: Vec<[u32; 3]> = data.faces.iter().map(|f| f.vertex_ids).collect();
+        let corner = |f: usize, k: usize| positions[faces[f][k] as usize];
+
+        let mut quadrics = vec![Quadric::default(); nverts];
+        let mut vertex_faces = vec![Vec::new(); nverts];
+        for (f, ids) in faces.iter().enumerate() {
+            let (a, b, c) = (corner(f, 0), corner(f, 1), corner(f, 2));
+            let n = cross(sub(c, a), sub(b, a));
+            let area = dot(n, n).sqrt() / 2.0;
+            if let Some(n) = normalize(n) {
+                let q = Quadric::plane(n, -dot(n, a), area);
+                for &v in ids.iter() {
+                    quadrics[v as usize] = quadrics[v as usize].add(&q);
+                }
+            }
+            for &v in ids.iter() {
+                vertex_faces[v as usize].push(f);
+            }
+        }
+
+        // Open borders are held in place by planes standing on them.
+        for e in adjacency.boundary_edges() {
+            let edge = adjacency.edge(e);
+            let face = edge.face_ids[0].min(edge.face_ids[1]);
+            if face == NO_ID {
+                continue;
+            }
+            let [u, v] = edge.vertex_ids;
+            let (pu, pv) = (positions[u as usize], positions[v as usize]);
+            let f = face as usize;
+            let face_normal = cross(
+                sub(corner(f, 2), corner(f, 0)),
+                sub(corner(f, 1), corner(f, 0)),
+            );
+            let along = sub(pv, pu);
+            if let Some(n) = normalize(cross(along, face_normal)) {
+                let q = Quadric::plane(n, -dot(n, pu), BOUNDARY_WEIGHT * dot(along, along));
+                quadrics[u as usize] = quadrics[u as usize].add(&q);
+                quadrics[v as usize] = quadrics[v as usize].add(&q);
+            }
+        }
+
+        // Vertices split for their uvs or normals must move together, so
+        // they do not move at all.
+        let mut at_position: HashMap<[u32; 3], usize> = HashMap::new();
+        for v in data.vertices.iter() {
+            let p = v.position;
+            *at_position
+                .entry([p[0].to_bits(), p[1].to_bits(), p[2].to_bits()])
+                .or_insert(0) += 1;
+        }
+        let locked = data
+            .vertices
+            .iter()
+            .map(|v| {
+                let p = v.position;
+                at_position[&[p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]] > 1
+            })
+            .collect();
+        let boundary = (0..nverts)
+            .map(|v| adjacency.is_boundary_vertex(v))
+            .collect();
+
+        let mut simplifier = Simplifier {
+            positions,
+            quadrics,
+            locked,
+            boundary,
+            versions: vec![0; nverts],
+            live: vec![true; faces.len()],
+            faces,
+            vertex_faces,
+            heap: BinaryHeap::new(),
+        };
+        for edge in data.edges.iter() {
+            let [u, v] = edge.vertex_ids;
+            simplifier.push(u as u32, v as u32);
+        }
+        Ok(simplifier)
+    }
+
+    /// Scores collapsing the edge `uv` and queues it.
+    fn push(&mut self, u: u32, v: u32) {
+        let (mut keep, mut remove) = (u as usize, v as usize);
+        if self.locked[remove] {
+            std::mem::swap(&mut keep, &mut remove);
+        }
+        if self.locked[remove] {
+            return;
+        }
+        let q = self.quadrics[keep].add(&self.quadrics[remove]);
+        let (pk, pr) = (self.positions[keep], self.positions[remove]);
+        let mid = [
+            (pk[0] + pr[0]) / 2.0,
+            (pk[1] + pr[1]) / 2.0,
+            (pk[2] + pr[2]) / 2.0,
+        ];
+
+        let mut options = vec![pk];
+        if !self.locked[keep] {
+            options.push(pr);
+            options.push(mid);
+            // Trust the optimum only near the edge; far off it is noise
+            // from nearly parallel planes.
+            let len2 = dot(sub(pk, pr), sub(pk, pr));
+            if let Some(p) = q
+                .optimum()
+                .filter(|&p| dot(sub(p, mid), sub(p, mid)) <= len2)
+            {
+                options.push(p);
+            }
+        }
+        let (cost, target) = options
+            .into_iter()
+            .map(|p| (q.error(p).max(0.0), p))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+            .unwrap();
+
+        self.heap.push(Collapse {
+            cost,
+            keep: keep as u32,
+            remove: remove as u32,
+            target,
+            versions: (self.versions[keep], self.versions[remove]),
+        });
+    }
+
+    fn live_faces(&self, v: usize) -> impl Iterator<Item = usize> + '_ {
+        self.vertex_faces[v]
+            .iter()
+            .cloned()
+            .filter(move |&f| self.live[f])
+    }
+
+    fn neighbours(&self, v: usize) -> Vec<u32> {
+        let mut n: Vec<u32> = self
+            .live_faces(v)
+            .flat_map(|f| self.faces[f].iter().cloned())
+            .filter(|&w| w as usize != v)
+            .collect();
+        n.sort();
+        n.dedup();
+        n
+    }
+
+    /// Whether the collapse keeps the surface a manifold without folding
+    /// any face over.
+    fn allowed(&self, c: &Collapse) -> bool {
+        let (keep, remove) = (c.keep as usize, c.remove as usize);
+        let shared_faces = self
+            .live_faces(keep)
+            .filter(|&f| self.faces[f].contains(&c.remove))
+            .count();
+        if shared_faces == 0 {
+            return false;
+        }
+        // Joining two borders through the inside would pinch the surface.
+        if self.boundary[keep] && self.boundary[remove] && shared_faces != 1 {
+            return false;
+        }
+        let kn = self.neighbours(keep);
+        let shared_neighbours = self
+            .neighbours(remove)
+            .iter()
+            .filter(|w| kn.binary_search(w).is_ok())
+            .count();
+        if shared_neighbours != shared_faces {
+            return false;
+        }
+
+        for &v in [keep, remove].iter() {
+            for f in self.live_faces(v) {
+                let ids = self.faces[f];
+                if ids.contains(&c.keep) && ids.contains(&c.remove) {
+                    continue;
+                }
+                let p = |k: usize| self.positions[ids[k] as usize];
+                let moved = |k: usize| {
+                    if ids[k] as usize == v {
+                        c.target
+                    } else {
+                        p(k)
+                    }
+                };
+                let before = cross(sub(p(2), p(0)), sub(p(1), p(0)));
+                let after = cross(sub(moved(2), moved(0)), sub(moved(1), moved(0)));
+                match (normalize(before), normalize(after)) {
+                    (Some(b), Some(a)) if dot(a, b) > 0.0 => {}
+                    (None, Some(_)) => {}
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+
+    fn collapse(&mut self, c: &Collapse) -> usize {
+        let (keep, remove) = (c.keep as usize, c.remove as usize);
+        let mut removed = 0;
+        let faces: Vec<usize> = self.live_faces(remove).collect();
+        for f in faces {
+            if self.faces[f].contains(&c.keep) {
+                self.live[f] = false;
+                removed += 1;
+            } else {
+                for id in self.faces[f].iter_mut().filter(|id| **id == c.remove) {
+                    *id = c.keep;
+                }
+                self.vertex_faces[keep].push(f);
+            }
+        }
+        self.vertex_faces[remove].clear();
+        let live = &self.live;
+        self.vertex_faces[keep].retain(|&f| live[f]);
+
+        self.positions[keep] = c.target;
+        self.quadrics[keep] = self.quadrics[keep].add(&self.quadrics[remove]);
+        self.boundary[keep] |= self.boundary[remove];
+        self.versions[keep] += 1;
+        self.versions[remove] += 1;
+        for w in self.neighbours(keep) {
+            self.push(c.keep, w);
+        }
+        removed
+    }
+
+    fn run(&mut self, target_faces: usize) {
+        let mut nfaces = self.faces.len();
+        while nfaces > target_faces {
+            let c = match self.heap.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            let (keep, remove) = (c.keep as usize, c.remove as usize);
+            if c.versions != (self.versions[keep], self.versions[remove]) || !self.allowed(&c) {
+                continue;
+            }
+            nfaces -= self.collapse(&c);
+        }
+    }
+}
+
+impl MeshData {
+    /// A copy with edges collapsed, cheapest first by quadric error, until
+    /// at most `target_faces` faces remain or no collapse is safe.
+    ///
+    /// Candidate edges come from the winged-edge data, built first if the
+    /// mesh has none. Open borders resist moving, and vertices split along
+    /// uv or normal seams stay put so the seams do not tear. Surviving
+    /// vertices keep their attributes and morph deltas.
+    pub fn simplify(&self, target_faces: usize) -> Result<MeshData, AdjacencyError> {
+        let mut data = self.clone();
+        if data.edges.is_empty() {
+            data.build_edges()?;
+        }
+        let mut simplifier = Simplifier::new(&data)?;
+        simplifier.run(target_faces);
+
+        let mut remap = vec![u32::MAX; data.vertices.len()];
+        let mut kept = Vec::new();
+        let mut faces = Vec::new();
+        let mut origin = Vec::new();
+        for (f, (ids, _)) in simplifier
+            .faces
+            .iter()
+            .zip(simplifier.live.iter())
+            .enumerate()
+            .filter(|(_, (_, &live))| live)
+        {
+            origin.push(f);
+            let mut face = [0; 3];
+            for (new, &v) in face.iter_mut().zip(ids.iter()) {
+                if remap[v as usize] == u32::MAX {
+                    remap[v as usize] = kept.len() as u32;
+                    kept.push(v as usize);
+                }
+                *new = remap[v as usize];
+            }
+            faces.push(Face::new(face));
+        }
+        for (v, p) in data.vertices.iter_mut().zip(simplifier.positions.iter()) {
+            v.position = [p[0] as f32, p[1] as f32, p[2] as f32];
+        }
+        data.faces = faces;
+        data.regroup_submeshes(&origin);
+        data.keep_vertices(&kept);
+        Ok(data)
+    }
+
+    /// Successively simpler copies, one per entry of `target_faces`, each
+    /// simplified from the one before.
+    pub fn lod_chain(&self, target_faces: &[usize]) -> Result<Vec<MeshData>, AdjacencyError> {
+        let mut levels: Vec<MeshData> = Vec::with_capacity(target_faces.len());
+        for &target in target_faces.iter() {
+            let level = levels.last().unwrap_or(self).simplify(target)?;
+            levels.push(level);
+        }
+        Ok(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplifies_closed_mesh() {
+        let sphere = MeshData::icosphere(1.0, 3);
+        let levels = sphere.lod_chain(&[320, 80]).unwrap();
+        assert!(levels[0].faces.len() <= 320 && levels[0].faces.len() > 300);
+        assert!(levels[1].faces.len() <= 80 && levels[1].faces.len() > 60);
+        for level in levels.iter() {
+            let adjacency = level.adjacency().unwrap();
+            assert_eq!(adjacency.boundary_edges().count(), 0);
+            for v in level.vertices.iter() {
+                let p = v.position();
+                let r = (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+                assert!(r > 0.85 && r < 1.05, "vertex at radius {}", r);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_borders_and_seams() {
+        // A flat grid simplifies to almost nothing, but its outline stays.
+        let plane = MeshData::plane(2.0, 2.0, 8, 8);
+        let simple = plane.simplify(2).unwrap();
+        assert!(simple.faces.len() < 20, "{} faces", simple.faces.len());
+        let max = |k: usize| {
+            simple
+                .vertices
+                .iter()
+                .map(|v| v.position()[k].abs())
+                .fold(0.0, f32::max)
+        };
+        assert!((max(0) - 1.0).abs() < 1e-4 && (max(2) - 1.0).abs() < 1e-4);
+
+        // Every vertex of a cube lies on a seam.
+        let cube = MeshData::cube(1.0);
+        assert_eq!(cube.simplify(0).unwrap().faces.len(), 12);
+    }
+}
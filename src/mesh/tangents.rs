@@ -0,0 +1,312 @@
+use super::layout::{AttribType, VertexAttrib, VertexLayout, VERTEX_ATTRIBUTES};
+use super::{face_indices, pack_snorm16, unpack_snorm16, Face, MeshData, Vertex};
+use std::collections::HashMap;
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn add_scaled(acc: &mut [f32; 3], v: [f32; 3], s: f32) {
+    for (a, v) in acc.iter_mut().zip(v.iter()) {
+        *a += v * s;
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: [f32; 3]) -> Option<[f32; 3]> {
+    let len = dot(a, a).sqrt();
+    if len > 1e-12 {
+        Some([a[0] / len, a[1] / len, a[2] / len])
+    } else {
+        None
+    }
+}
+
+/// The angle of a triangle at `p`, between the edges to `q` and `r`.
+fn corner_angle(p: [f32; 3], q: [f32; 3], r: [f32; 3]) -> f32 {
+    match (normalize(sub(q, p)), normalize(sub(r, p))) {
+        (Some(a), Some(b)) => dot(a, b).clamp(-1.0, 1.0).acos(),
+        _ => 0.0,
+    }
+}
+
+/// The front-facing unit normal of a clockwise face, if it has any area.
+fn face_normal(data: &MeshData, f: &Face) -> Option<[f32; 3]> {
+    let [a, b, c] = f.vertex_ids;
+    let p = |i: u32| data.vertices[i as usize].position;
+    normalize(cross(sub(p(c), p(a)), sub(p(b), p(a))))
+}
+
+/// Calls `visit` with the vertex id and angle of each corner of `f`.
+fn for_each_corner(data: &MeshData, f: &Face, mut visit: impl FnMut(usize, f32)) {
+    let ids = f.vertex_ids;
+    let p = |k: usize| data.vertices[ids[k] as usize].position;
+    for (k, &id) in ids.iter().enumerate() {
+        let angle = corner_angle(p(k), p((k + 1) % 3), p((k + 2) % 3));
+        visit(id as usize, angle);
+    }
+}
+
+impl MeshData {
+    /// Replaces every vertex normal with the average of the normals of the
+    /// faces around it, weighted by each face's angle at the vertex.
+    ///
+    /// Vertices at the same position are averaged together, so seams split
+    /// only for their uvs or colors do not show.
+    pub fn compute_smooth_normals(&mut self) {
+        let mut groups: HashMap<[u32; 3], usize> = HashMap::new();
+        let group: Vec<usize> = self
+            .vertices
+            .iter()
+            .map(|v| {
+                let key = [
+                    v.position[0].to_bits(),
+                    v.position[1].to_bits(),
+                    v.position[2].to_bits(),
+                ];
+                let next = groups.len();
+                *groups.entry(key).or_insert(next)
+            })
+            .collect();
+
+        let mut sums = vec![[0.0; 3]; groups.len()];
+        for f in self.faces.iter() {
+            if let Some(n) = face_normal(self, f) {
+                for_each_corner(self, f, |v, angle| {
+                    add_scaled(&mut sums[group[v]], n, angle)
+                });
+            }
+        }
+
+        for (v, g) in self.vertices.iter_mut().zip(group.iter()) {
+            if let Some(n) = normalize(sums[*g]) {
+                v.set_normal(n);
+            }
+        }
+    }
+
+    /// Gives every face its own three vertices, with the face's normal.
+    /// Morph target deltas follow their vertices.
+    ///
+    /// The edges no longer match and are cleared; `build_edges` rebuilds
+    /// them.
+    pub fn compute_flat_normals(&mut self) {
+        let old_ids: Vec<usize> = face_indices(&self.faces)
+            .iter()
+            .map(|&i| i as usize)
+            .collect();
+        let mut vertices = Vec::with_capacity(old_ids.len());
+        for f in self.faces.iter() {
+            let n = face_normal(self, f);
+            for &i in f.vertex_ids.iter() {
+                let mut v = self.vertices[i as usize].clone();
+                if let Some(n) = n {
+                    v.set_normal(n);
+                }
+                vertices.push(v);
+            }
+        }
+        for target in self.morph_targets.iter_mut() {
+            let remap = |d: &[[f32; 3]]| old_ids.iter().map(|&i| d[i]).collect();
+            target.positions = remap(&target.positions);
+            if !target.normals.is_empty() {
+                target.normals = remap(&target.normals);
+            }
+        }
+
+        let nfaces = self.faces.len() as u32;
+        self.vertices = vertices;
+        self.faces = (0..nfaces)
+            .map(|f| Face::new([3 * f, 3 * f + 1, 3 * f + 2]))
+            .collect();
+        self.edges.clear();
+    }
+
+    /// Per-vertex tangents for normal mapping, following the MikkTSpace
+    /// conventions: xyz is the direction of increasing u, made orthogonal to
+    /// the normal, and w is the sign to apply to `cross(normal, tangent)` to
+    /// get the direction of increasing v.
+    ///
+    /// Contributions are angle-weighted per vertex, so results match
+    /// MikkTSpace as long as vertices are split wherever uvs or normals are.
+    pub fn compute_tangents(&self) -> Vec<[f32; 4]> {
+        let n = self.vertices.len();
+        let mut tangents = vec![[0.0; 3]; n];
+        let mut bitangents = vec![[0.0; 3]; n];
+        for f in self.faces.iter() {
+            let [a, b, c] = f.vertex_ids;
+            let v = |i: u32| &self.vertices[i as usize];
+            let e1 = sub(v(b).position, v(a).position);
+            let e2 = sub(v(c).position, v(a).position);
+            let (uva, uvb, uvc) = (v(a).uv(), v(b).uv(), v(c).uv());
+            let (du1, dv1) = (uvb[0] - uva[0], uvb[1] - uva[1]);
+            let (du2, dv2) = (uvc[0] - uva[0], uvc[1] - uva[1]);
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let mut t = [0.0; 3];
+            add_scaled(&mut t, e1, dv2 / det);
+            add_scaled(&mut t, e2, -dv1 / det);
+            let mut b = [0.0; 3];
+            add_scaled(&mut b, e2, du1 / det);
+            add_scaled(&mut b, e1, -du2 / det);
+            let (t, b) = match (normalize(t), normalize(b)) {
+                (Some(t), Some(b)) => (t, b),
+                _ => continue,
+            };
+            for_each_corner(self, f, |i, angle| {
+                add_scaled(&mut tangents[i], t, angle);
+                add_scaled(&mut bitangents[i], b, angle);
+            });
+        }
+
+        self.vertices
+            .iter()
+            .zip(tangents.iter().zip(bitangents.iter()))
+            .map(|(v, (&t, &b))| {
+                let n = v.normal();
+                let mut ortho = t;
+                add_scaled(&mut ortho, n, -dot(n, t));
+                // Any direction in the surface will do where uvs give none.
+                let t = normalize(ortho)
+                    .or_else(|| normalize(cross(n, [0.0, 1.0, 0.0])))
+                    .or_else(|| normalize(cross(n, [1.0, 0.0, 0.0])))
+                    .unwrap_or([1.0, 0.0, 0.0]);
+                let w = if dot(cross(n, t), b) < 0.0 { -1.0 } else { 1.0 };
+                [t[0], t[1], t[2], w]
+            })
+            .collect()
+    }
+
+    /// The vertices with tangents from `compute_tangents`, ready to upload.
+    pub fn tangent_vertices(&self) -> Vec<TangentVertex> {
+        self.vertices
+            .iter()
+            .zip(self.compute_tangents().iter())
+            .map(|(v, &t)| TangentVertex::new(v.clone(), t))
+            .collect()
+    }
+}
+
+/// A `Vertex` followed by a tangent, for normal-mapped meshes. Programs
+/// drawing it need `set_vertex_layout::<TangentVertex>()` to bind `tangent`.
+#[derive(Default, Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct TangentVertex {
+    pub vertex: Vertex,
+    tangent: [i16; 4],
+}
+
+impl TangentVertex {
+    pub fn new(vertex: Vertex, tangent: [f32; 4]) -> Self {
+        TangentVertex {
+            vertex,
+            tangent: [
+                pack_snorm16(tangent[0]),
+                pack_snorm16(tangent[1]),
+                pack_snorm16(tangent[2]),
+                pack_snorm16(tangent[3]),
+            ],
+        }
+    }
+
+    pub fn tangent(&self) -> [f32; 4] {
+        [
+            unpack_snorm16(self.tangent[0]),
+            unpack_snorm16(self.tangent[1]),
+            unpack_snorm16(self.tangent[2]),
+            unpack_snorm16(self.tangent[3]),
+        ]
+    }
+}
+
+const TANGENT_VERTEX_ATTRIBUTES: [VertexAttrib; 7] = [
+    VERTEX_ATTRIBUTES[0],
+    VERTEX_ATTRIBUTES[1],
+    VERTEX_ATTRIBUTES[2],
+    VERTEX_ATTRIBUTES[3],
+    VERTEX_ATTRIBUTES[4],
+    VERTEX_ATTRIBUTES[5],
+    VertexAttrib {
+        name: "tangent",
+        location: 6,
+        kind: AttribType::Short,
+        count: 4,
+        normalized: true,
+        offset: 32,
+    },
+];
+
+impl VertexLayout for TangentVertex {
+    fn attributes() -> &'static [VertexAttrib] {
+        &TANGENT_VERTEX_ATTRIBUTES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::layout::check_layout;
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-3)
+    }
+
+    #[test]
+    fn averages_normals_by_angle() {
+        let mut cube = MeshData::cube(2.0);
+        cube.compute_smooth_normals();
+        let d = 1.0 / 3f32.sqrt();
+        for v in cube.vertices.iter() {
+            let p = v.position();
+            assert!(close(&v.normal(), &[p[0] * d, p[1] * d, p[2] * d]));
+        }
+
+        cube.compute_flat_normals();
+        assert_eq!(cube.vertices.len(), 36);
+        for f in cube.faces.iter() {
+            let normals: Vec<_> = f
+                .vertex_ids()
+                .iter()
+                .map(|&i| cube.vertices[i as usize].normal())
+                .collect();
+            assert!(normals.iter().all(|n| close(n, &normals[0])));
+            assert_eq!(normals[0].iter().filter(|c| c.abs() > 0.5).count(), 1);
+        }
+    }
+
+    #[test]
+    fn computes_tangent_frames() {
+        let quad = MeshData::quad(2.0, 2.0);
+        for t in quad.compute_tangents().iter() {
+            assert!(close(t, &[1.0, 0.0, 0.0, 1.0]));
+        }
+
+        // Mirroring u flips both the tangent and its handedness.
+        let mut mirrored = quad;
+        for v in mirrored.vertices.iter_mut() {
+            let uv = v.uv();
+            v.set_uv([1.0 - uv[0], uv[1]]);
+        }
+        for t in mirrored.compute_tangents().iter() {
+            assert!(close(t, &[-1.0, 0.0, 0.0, -1.0]));
+        }
+
+        let packed = mirrored.tangent_vertices();
+        assert!(close(&packed[0].tangent(), &[-1.0, 0.0, 0.0, -1.0]));
+        assert_eq!(TangentVertex::stride(), 40);
+        assert_eq!(check_layout::<TangentVertex>(), Ok(()));
+    }
+}
@@ -43,6 +43,9 @@ pub struct Program {
     vertex_shader: Option<Shader>,
     geometry_shader: Option<Shader>,
     fragment_shader: Option<Shader>,
+    attributes: &'static [VertexAttrib],
+    instance_attributes: &'static [VertexAttrib],
+    morph_attributes: &'static [VertexAttrib],
 }
 
 impl Drop for Program {
@@ -63,6 +66,9 @@ impl Program {
                 vertex_shader: None,
                 geometry_shader: None,
                 fragment_shader: None,
+                attributes: Vertex::attributes(),
+                instance_attributes: Instance::attributes(),
+                morph_attributes: MorphVertex::attributes(),
             }
         }
     }
@@ -100,6 +106,18 @@ impl Program {
         Ok(())
     }
 
+    /// Binds attribute names to the locations used by `V` instead of
+    /// `Vertex`. Takes effect on the next `build`.
+    pub fn set_vertex_layout<V: VertexLayout>(&mut self) {
+        self.attributes = V::attributes();
+    }
+
+    /// Binds per-instance attribute names to the locations used by `I`
+    /// instead of `Instance`. Takes effect on the next `build`.
+    pub fn set_instance_layout<I: VertexLayout>(&mut self) {
+        self.instance_attributes = I::attributes();
+    }
+
     unsafe fn get_link_error(&self) -> Result<(), ProgramError> {
         let mut err: GLint = -1;
         let mut len = 0;
@@ -137,10 +155,15 @@ impl Program {
                 }
             }
 
-            gl::BindAttribLocation(self.id, 0, CString::new("position").unwrap().as_ptr());
-            gl::BindAttribLocation(self.id, 1, CString::new("normal").unwrap().as_ptr());
-            gl::BindAttribLocation(self.id, 2, CString::new("uv").unwrap().as_ptr());
-            gl::BindAttribLocation(self.id, 3, CString::new("color").unwrap().as_ptr());
+            let attributes = self
+                .attributes
+                .iter()
+                .chain(self.instance_attributes)
+                .chain(self.morph_attributes);
+            for attrib in attributes.filter(|a| !a.name.is_empty()) {
+                let name = CString::new(attrib.name).unwrap();
+                gl::BindAttribLocation(self.id, attrib.location, name.as_ptr());
+            }
 
             gl::LinkProgram(self.id);
             self.get_link_error()?;
@@ -197,6 +220,14 @@ impl Program {
         }
     }
 
+    pub fn set_uniform_mat4_array(&self, name: &str, u: &[Matrix4<f32>]) {
+        unsafe {
+            self.bind();
+            let loc = gl::GetUniformLocation(self.id, CString::new(name).unwrap().as_ptr());
+            gl::UniformMatrix4fv(loc, u.len() as GLsizei, gl::FALSE, u.as_ptr() as *const GLfloat);
+        }
+    }
+
     pub fn set_uniform_vec2(&self, name: &str, u: &Vector2<f32>) {
         unsafe {
             self.bind();
@@ -242,4 +273,18 @@ impl Program {
         mesh.bind();
         mesh.draw();
     }
+
+    /// Draws one of `mesh.submeshes()`, e.g. after binding its material's
+    /// texture.
+    pub fn draw_submesh(&mut self, mesh: &Mesh, submesh: usize) {
+        self.bind();
+        mesh.bind();
+        mesh.draw_submesh(submesh);
+    }
+
+    pub fn draw_instanced(&mut self, draw: &InstancedDraw) {
+        self.bind();
+        draw.mesh().bind();
+        draw.draw();
+    }
 }
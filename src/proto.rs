@@ -0,0 +1,4 @@
+/// Types generated from `src/proto/model.proto`.
+pub mod model {
+    include!(concat!(env!("OUT_DIR"), "/rockwork.model.rs"));
+}
@@ -0,0 +1,327 @@
+use crate::bounds::Aabb;
+use crate::mesh::MeshData;
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+/// A half-line from `origin` along the unit vector `dir`, so hit
+/// parameters are distances.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub dir: Vector3<f32>,
+}
+
+/// Where a ray meets a mesh.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub face: usize,
+    /// Weights of the face's three vertices, in `vertex_ids` order.
+    pub barycentric: [f32; 3],
+    pub distance: f32,
+}
+
+fn unproject(inverse: &Matrix4<f32>, x: f32, y: f32, z: f32) -> Option<Point3<f32>> {
+    let p = inverse * Vector4::new(x, y, z, 1.0);
+    if p.w.abs() < 1e-12 {
+        return None;
+    }
+    Some(Point3::new(p.x / p.w, p.y / p.w, p.z / p.w))
+}
+
+impl Ray {
+    /// Normalizes `dir`.
+    pub fn new(origin: Point3<f32>, dir: Vector3<f32>) -> Self {
+        Ray {
+            origin,
+            dir: dir.normalize(),
+        }
+    }
+
+    /// The ray through pixel `(x, y)` of a `width` by `height` viewport,
+    /// measured from the top left like window events, for a camera with
+    /// view-projection `view_proj`. `None` if the matrix is singular.
+    pub fn from_screen(
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        view_proj: &Matrix4<f32>,
+    ) -> Option<Self> {
+        let inverse = view_proj.try_inverse()?;
+        let nx = 2.0 * x / width - 1.0;
+        let ny = 1.0 - 2.0 * y / height;
+        let near = unproject(&inverse, nx, ny, -1.0)?;
+        let far = unproject(&inverse, nx, ny, 1.0)?;
+        Some(Ray::new(near, far - near))
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.dir * t
+    }
+
+    /// Möller–Trumbore intersection with triangle `abc` from either side.
+    /// Returns the distance and the barycentric weights of `a`, `b`, `c`.
+    pub fn intersect_triangle(
+        &self,
+        a: &Point3<f32>,
+        b: &Point3<f32>,
+        c: &Point3<f32>,
+    ) -> Option<(f32, [f32; 3])> {
+        let e1 = b - a;
+        let e2 = c - a;
+        let p = self.dir.cross(&e2);
+        let det = e1.dot(&p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(&p) * inv;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&e1);
+        let v = self.dir.dot(&q) * inv;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&q) * inv;
+        if t < 0.0 {
+            return None;
+        }
+        Some((t, [1.0 - u - v, u, v]))
+    }
+
+    /// The distance at which the ray enters `b`, 0 if it starts inside.
+    pub fn intersect_aabb(&self, b: &Aabb) -> Option<f32> {
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for i in 0..3 {
+            let inv = 1.0 / self.dir[i];
+            let mut t0 = (b.min[i] - self.origin[i]) * inv;
+            let mut t1 = (b.max[i] - self.origin[i]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN from 0 * inf leaves the bound alone.
+            near = if t0 > near { t0 } else { near };
+            far = if t1 < far { t1 } else { far };
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+fn corners(data: &MeshData, face: usize) -> [Point3<f32>; 3] {
+    let ids = data.faces[face].vertex_ids();
+    let p = |k: usize| {
+        let p = data.vertices[ids[k] as usize].position();
+        Point3::new(p[0], p[1], p[2])
+    };
+    [p(0), p(1), p(2)]
+}
+
+fn hit_face(data: &MeshData, face: usize, ray: &Ray) -> Option<Hit> {
+    let [a, b, c] = corners(data, face);
+    ray.intersect_triangle(&a, &b, &c)
+        .map(|(distance, barycentric)| Hit {
+            face,
+            barycentric,
+            distance,
+        })
+}
+
+fn nearer(a: Option<Hit>, b: Option<Hit>) -> Option<Hit> {
+    match (a, b) {
+        (Some(a), Some(b)) if b.distance < a.distance => Some(b),
+        (Some(a), _) => Some(a),
+        (None, b) => b,
+    }
+}
+
+impl MeshData {
+    /// The nearest face hit by `ray`, testing every face. Build a `Bvh` for
+    /// large meshes or repeated queries.
+    pub fn raycast(&self, ray: &Ray) -> Option<Hit> {
+        (0..self.faces.len())
+            .map(|f| hit_face(self, f, ray))
+            .fold(None, nearer)
+    }
+}
+
+const LEAF_FACES: usize = 4;
+
+#[derive(Clone, Debug)]
+struct BvhNode {
+    aabb: Aabb,
+    /// Leaves cover `faces[first..first + count]`; inner nodes have
+    /// `count` 0 and children at `first` and `first + 1`.
+    first: usize,
+    count: usize,
+}
+
+/// A bounding volume hierarchy over the faces of a `MeshData`, for
+/// raycasts in logarithmic time. Rebuild it when the mesh changes.
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    faces: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(data: &MeshData) -> Self {
+        let boxes: Vec<Aabb> = (0..data.faces.len())
+            .map(|f| Aabb::from_points(corners(data, f).iter()))
+            .collect();
+        let mut bvh = Bvh {
+            nodes: vec![],
+            faces: (0..data.faces.len()).collect(),
+        };
+        bvh.nodes.push(BvhNode {
+            aabb: Aabb::empty(),
+            first: 0,
+            count: data.faces.len(),
+        });
+        bvh.split(0, &boxes);
+        bvh
+    }
+
+    /// Bounds node `n` and splits it at the median centroid along its
+    /// longest axis until leaves are small.
+    fn split(&mut self, n: usize, boxes: &[Aabb]) {
+        let (first, count) = (self.nodes[n].first, self.nodes[n].count);
+        let faces = &mut self.faces[first..first + count];
+        let aabb = faces
+            .iter()
+            .fold(Aabb::empty(), |acc, &f| acc.union(&boxes[f]));
+        self.nodes[n].aabb = aabb;
+        if count <= LEAF_FACES {
+            return;
+        }
+
+        let size = aabb.max - aabb.min;
+        let axis = (0..3).max_by(|&i, &j| size[i].total_cmp(&size[j])).unwrap();
+        let centroid = |f: &usize| boxes[*f].min[axis] + boxes[*f].max[axis];
+        faces.sort_by(|a, b| centroid(a).total_cmp(&centroid(b)));
+
+        let half = count / 2;
+        let children = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb: Aabb::empty(),
+            first,
+            count: half,
+        });
+        self.nodes.push(BvhNode {
+            aabb: Aabb::empty(),
+            first: first + half,
+            count: count - half,
+        });
+        self.nodes[n].first = children;
+        self.nodes[n].count = 0;
+        self.split(children, boxes);
+        self.split(children + 1, boxes);
+    }
+
+    /// The nearest face of `data` hit by `ray`. `data` must be the mesh
+    /// the hierarchy was built from.
+    pub fn raycast(&self, data: &MeshData, ray: &Ray) -> Option<Hit> {
+        let mut best: Option<Hit> = None;
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            let t = match ray.intersect_aabb(&node.aabb) {
+                Some(t) => t,
+                None => continue,
+            };
+            if matches!(best, Some(h) if h.distance < t) {
+                continue;
+            }
+            if node.count > 0 {
+                for &f in self.faces[node.first..node.first + node.count].iter() {
+                    best = nearer(best, hit_face(data, f, ray));
+                }
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_rays_from_screen() {
+        let proj = Matrix4::new_perspective(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let view = Matrix4::look_at_rh(
+            &Point3::new(0.0, 0.0, 10.0),
+            &Point3::origin(),
+            &Vector3::y(),
+        );
+        let center = Ray::from_screen(50.0, 50.0, 100.0, 100.0, &(proj * view)).unwrap();
+        assert!((center.dir - Vector3::new(0.0, 0.0, -1.0)).norm() < 1e-4);
+        assert!((center.origin - Point3::new(0.0, 0.0, 9.9)).norm() < 1e-3);
+
+        // The top right corner of a 90° view.
+        let corner = Ray::from_screen(100.0, 0.0, 100.0, 100.0, &(proj * view)).unwrap();
+        let expected = Vector3::new(1.0, 1.0, -1.0).normalize();
+        assert!((corner.dir - expected).norm() < 1e-4);
+    }
+
+    #[test]
+    fn picks_nearest_face() {
+        let sphere = MeshData::icosphere(1.0, 3);
+        let bvh = Bvh::new(&sphere);
+        let ray = Ray::new(Point3::new(0.3, 0.2, 5.0), Vector3::new(0.0, 0.0, -1.0));
+
+        let hit = sphere.raycast(&ray).unwrap();
+        assert_eq!(bvh.raycast(&sphere, &ray), Some(hit));
+        assert!(hit.distance > 4.0 && hit.distance < 4.1);
+        assert!((hit.barycentric.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        let [a, b, c] = corners(&sphere, hit.face);
+        let [wa, wb, wc] = hit.barycentric;
+        let p = Point3::origin() + a.coords * wa + b.coords * wb + c.coords * wc;
+        assert!((p - ray.at(hit.distance)).norm() < 1e-4);
+
+        let miss = Ray::new(Point3::new(2.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(sphere.raycast(&miss), None);
+        assert_eq!(bvh.raycast(&sphere, &miss), None);
+
+        // Every direction from the center agrees with the brute force, though
+        // rays through a vertex may pick different faces around it.
+        let inside = Point3::origin();
+        for i in 0..50 {
+            let a = i as f32 * 0.7;
+            let ray = Ray::new(inside, Vector3::new(a.cos(), (a * 1.3).sin(), a.sin()));
+            let (fast, slow) = (bvh.raycast(&sphere, &ray), sphere.raycast(&ray));
+            assert_eq!(fast.map(|h| h.distance), slow.map(|h| h.distance));
+        }
+
+        // Faces spanning -inf to +inf have NaN centroids, which must not
+        // bring the build down.
+        let mut broken = sphere.clone();
+        for (i, v) in broken.vertices.iter_mut().enumerate() {
+            let [_, y, z] = v.position();
+            let x = if i % 2 == 0 {
+                f32::INFINITY
+            } else {
+                f32::NEG_INFINITY
+            };
+            v.set_position([x, y, z]);
+        }
+        broken.vertices[1].set_position([f32::NAN; 3]);
+        let spans = |f: &crate::mesh::Face| {
+            let xs: Vec<f32> = f
+                .vertex_ids()
+                .iter()
+                .map(|&v| broken.vertices[v as usize].position()[0])
+                .collect();
+            xs.contains(&f32::INFINITY) && xs.contains(&f32::NEG_INFINITY)
+        };
+        assert!(broken.faces.iter().any(spans));
+        Bvh::new(&broken);
+    }
+}
@@ -0,0 +1,814 @@
+use crate::gltf::{GltfChannelValues, GltfInterpolation, GltfNode, GltfScene};
+use crate::program::Program;
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
+use std::{error, fmt};
+
+/// Size of the `bone_matrices` uniform array in `glsl/skinned.vs`.
+pub const MAX_BONES: usize = 64;
+
+/// Uniform that `upload_palette` writes skinning matrices to.
+pub const BONE_MATRICES_UNIFORM: &str = "bone_matrices";
+
+#[derive(Debug, PartialEq)]
+pub enum SkeletonError {
+    TooManyBones(usize),
+    BadParent(usize),
+    Cycle(usize),
+    BadSkin(usize),
+}
+
+impl fmt::Display for SkeletonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkeletonError::TooManyBones(n) => {
+                write!(f, "skeleton has {} bones, limit is {}", n, MAX_BONES)
+            }
+            SkeletonError::BadParent(b) => write!(f, "bone {} has an out of range parent", b),
+            SkeletonError::Cycle(b) => write!(f, "bone {} is its own ancestor", b),
+            SkeletonError::BadSkin(s) => write!(f, "glTF scene has no skin {}", s),
+        }
+    }
+}
+
+impl error::Error for SkeletonError {}
+
+/// A decomposed local transform.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.0),
+        }
+    }
+}
+
+fn lerp_vec3(a: &Vector3<f32>, b: &Vector3<f32>, t: f32) -> Vector3<f32> {
+    a * (1.0 - t) + b * t
+}
+
+/// Normalized lerp along the shorter arc.
+fn nlerp(a: &UnitQuaternion<f32>, b: &UnitQuaternion<f32>, t: f32) -> UnitQuaternion<f32> {
+    let qa = *a.quaternion();
+    let mut qb = *b.quaternion();
+    if qa.coords.dot(&qb.coords) < 0.0 {
+        qb = -qb;
+    }
+    UnitQuaternion::new_normalize(qa * (1.0 - t) + qb * t)
+}
+
+/// Builds a rotation from x, y, z, w components, as glTF stores them.
+fn quat_xyzw(q: [f32; 4]) -> UnitQuaternion<f32> {
+    UnitQuaternion::new_normalize(Quaternion::new(q[3], q[0], q[1], q[2]))
+}
+
+fn node_transform(node: &GltfNode) -> Transform {
+    Transform {
+        translation: Vector3::from(node.translation),
+        rotation: quat_xyzw(node.rotation),
+        scale: Vector3::from(node.scale),
+    }
+}
+
+/// The joint nearest above `node`, and the transforms of the non-joint
+/// nodes in between combined, such as an exporter's armature node.
+fn joint_parent(scene: &GltfScene, joints: &[usize], node: usize) -> (Option<usize>, Transform) {
+    let mut between = Transform::default();
+    let mut ancestor = scene.nodes[node].parent;
+    while let Some(a) = ancestor {
+        if let Some(p) = joints.iter().position(|&j| j == a) {
+            return (Some(p), between);
+        }
+        between = node_transform(&scene.nodes[a]).compose(&between);
+        ancestor = scene.nodes[a].parent;
+    }
+    (None, between)
+}
+
+impl Transform {
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    /// `child` placed under `self`, as if parented to it. Exact unless
+    /// `self` scales non-uniformly and `child` rotates.
+    pub fn compose(&self, child: &Transform) -> Transform {
+        Transform {
+            translation: self.translation
+                + self.rotation * self.scale.component_mul(&child.translation),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale.component_mul(&child.scale),
+        }
+    }
+
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: lerp_vec3(&self.translation, &other.translation, t),
+            rotation: nlerp(&self.rotation, &other.rotation, t),
+            scale: lerp_vec3(&self.scale, &other.scale, t),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bone {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Local transform in the bind pose.
+    pub rest: Transform,
+    /// Takes mesh space into this bone's space in the bind pose.
+    pub inverse_bind: Matrix4<f32>,
+}
+
+impl Bone {
+    pub fn new(name: &str, parent: Option<usize>, rest: Transform) -> Self {
+        Bone {
+            name: name.to_string(),
+            parent,
+            rest,
+            inverse_bind: Matrix4::identity(),
+        }
+    }
+}
+
+/// A bone hierarchy. Bone indices match the `boneid`s stored in `Vertex`.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    bones: Vec<Bone>,
+    /// Bone indices ordered so that every parent precedes its children.
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn new(bones: Vec<Bone>) -> Result<Self, SkeletonError> {
+        if bones.len() > MAX_BONES {
+            return Err(SkeletonError::TooManyBones(bones.len()));
+        }
+        if let Some(b) = bones
+            .iter()
+            .position(|b| matches!(b.parent, Some(p) if p >= bones.len()))
+        {
+            return Err(SkeletonError::BadParent(b));
+        }
+
+        // Depth-first from each bone to its root, emitting ancestors first.
+        let mut order = Vec::with_capacity(bones.len());
+        let mut placed = vec![false; bones.len()];
+        for start in 0..bones.len() {
+            let mut chain = Vec::new();
+            let mut b = Some(start);
+            while let Some(i) = b {
+                if placed[i] {
+                    break;
+                }
+                if chain.contains(&i) {
+                    return Err(SkeletonError::Cycle(i));
+                }
+                chain.push(i);
+                b = bones[i].parent;
+            }
+            for &i in chain.iter().rev() {
+                placed[i] = true;
+                order.push(i);
+            }
+        }
+
+        Ok(Skeleton { bones, order })
+    }
+
+    /// Builds the skeleton for one of `scene`'s skins. Bone `i` is joint `i`
+    /// of the skin, so vertex bone ids from the same file line up.
+    ///
+    /// A joint's parent is its nearest ancestor node that is also a joint.
+    /// The transforms of any non-joint nodes in between, like the armature
+    /// node Blender exports above the root joint, are folded into the
+    /// joint's rest transform.
+    pub fn from_gltf(scene: &GltfScene, skin: usize) -> Result<Self, SkeletonError> {
+        let skin = scene.skins.get(skin).ok_or(SkeletonError::BadSkin(skin))?;
+        let bones = skin
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(i, &node_id)| {
+                let node = &scene.nodes[node_id];
+                let (parent, between) = joint_parent(scene, &skin.joints, node_id);
+                let rest = between.compose(&node_transform(node));
+                let ibm = skin.inverse_bind_matrices[i];
+                let mut bone = Bone::new(&node.name, parent, rest);
+                bone.inverse_bind = Matrix4::from_fn(|r, c| ibm[c][r]);
+                bone
+            })
+            .collect();
+        Self::new(bones)
+    }
+
+    pub fn bones(&self) -> &[Bone] {
+        &self.bones
+    }
+
+    pub fn len(&self) -> usize {
+        self.bones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bones.is_empty()
+    }
+
+    pub fn find_bone(&self, name: &str) -> Option<usize> {
+        self.bones.iter().position(|b| b.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            locals: self.bones.iter().map(|b| b.rest).collect(),
+        }
+    }
+
+    /// Makes the rest pose the bind pose by recomputing every
+    /// `inverse_bind`. Handy for skeletons built in code.
+    pub fn bind_rest_pose(&mut self) {
+        let globals = self.rest_pose().global_matrices(self);
+        for (bone, global) in self.bones.iter_mut().zip(globals.iter()) {
+            bone.inverse_bind = global.try_inverse().unwrap_or_else(Matrix4::identity);
+        }
+    }
+}
+
+/// Local transforms for every bone of a skeleton.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub locals: Vec<Transform>,
+}
+
+impl Pose {
+    /// Interpolates towards `other`; `t` of 0 is `self`, 1 is `other`.
+    pub fn blend(&self, other: &Pose, t: f32) -> Pose {
+        Pose {
+            locals: self
+                .locals
+                .iter()
+                .zip(other.locals.iter())
+                .map(|(a, b)| a.lerp(b, t))
+                .collect(),
+        }
+    }
+
+    /// Bone to model space for each bone.
+    pub fn global_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        let mut globals = vec![Matrix4::identity(); self.locals.len()];
+        for &i in skeleton.order.iter() {
+            let local = self.locals[i].to_matrix();
+            globals[i] = match skeleton.bones[i].parent {
+                Some(p) => globals[p] * local,
+                None => local,
+            };
+        }
+        globals
+    }
+
+    /// The matrix palette for GPU skinning: bind-pose mesh space to posed
+    /// mesh space, for each bone.
+    pub fn skinning_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        self.global_matrices(skeleton)
+            .iter()
+            .zip(skeleton.bones.iter())
+            .map(|(g, b)| g * b.inverse_bind)
+            .collect()
+    }
+}
+
+/// Writes a palette from `Pose::skinning_matrices` to `bone_matrices`.
+pub fn upload_palette(program: &Program, palette: &[Matrix4<f32>]) {
+    let n = palette.len().min(MAX_BONES);
+    program.set_uniform_mat4_array(BONE_MATRICES_UNIFORM, &palette[..n]);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+}
+
+/// Keyframes for one property of one bone. `times` are ascending seconds.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    pub times: Vec<f32>,
+    pub values: Vec<T>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Copy> Track<T> {
+    fn sample(&self, time: f32, lerp: impl Fn(&T, &T, f32) -> T) -> Option<T> {
+        let last = self.times.len().min(self.values.len()).checked_sub(1)?;
+        let next = self.times[..=last]
+            .iter()
+            .position(|&t| t > time)
+            .unwrap_or(last + 1);
+        if next == 0 {
+            return Some(self.values[0]);
+        }
+        if next > last {
+            return Some(self.values[last]);
+        }
+
+        let prev = next - 1;
+        match self.interpolation {
+            Interpolation::Step => Some(self.values[prev]),
+            Interpolation::Linear => {
+                let span = self.times[next] - self.times[prev];
+                let t = if span > 0.0 {
+                    (time - self.times[prev]) / span
+                } else {
+                    0.0
+                };
+                Some(lerp(&self.values[prev], &self.values[next], t))
+            }
+        }
+    }
+}
+
+/// Animated properties of one bone; anything missing keeps the pose's value.
+#[derive(Clone, Debug)]
+pub struct BoneTrack {
+    pub bone: usize,
+    pub translation: Option<Track<Vector3<f32>>>,
+    pub rotation: Option<Track<UnitQuaternion<f32>>>,
+    pub scale: Option<Track<Vector3<f32>>>,
+}
+
+impl BoneTrack {
+    pub fn new(bone: usize) -> Self {
+        BoneTrack {
+            bone,
+            translation: None,
+            rotation: None,
+            scale: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub tracks: Vec<BoneTrack>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str, tracks: Vec<BoneTrack>) -> Self {
+        let end = |times: Option<&Vec<f32>>| times.and_then(|t| t.last()).cloned();
+        let duration = tracks
+            .iter()
+            .flat_map(|t| {
+                vec![
+                    end(t.translation.as_ref().map(|k| &k.times)),
+                    end(t.rotation.as_ref().map(|k| &k.times)),
+                    end(t.scale.as_ref().map(|k| &k.times)),
+                ]
+            })
+            .flatten()
+            .fold(0.0, f32::max);
+        AnimationClip {
+            name: name.to_string(),
+            duration,
+            tracks,
+        }
+    }
+
+    /// Converts one of `scene`'s animations for a skeleton built from `skin`.
+    /// Channels on nodes outside the skin and morph weight channels (see
+    /// `MorphWeightTrack`) are skipped; cubic spline keys keep only their
+    /// values and play linearly. Keys are folded with non-joint ancestors
+    /// the same way as in `Skeleton::from_gltf`.
+    pub fn from_gltf(scene: &GltfScene, animation: usize, skin: usize) -> Self {
+        let anim = &scene.animations[animation];
+        let joints = &scene.skins[skin].joints;
+        let mut tracks: Vec<BoneTrack> = Vec::new();
+        for channel in anim.channels.iter() {
+            let bone = match joints.iter().position(|&j| j == channel.node) {
+                Some(b) => b,
+                None => continue,
+            };
+            let (interpolation, stride, offset) = match channel.interpolation {
+                GltfInterpolation::Step => (Interpolation::Step, 1, 0),
+                GltfInterpolation::Linear => (Interpolation::Linear, 1, 0),
+                GltfInterpolation::CubicSpline => (Interpolation::Linear, 3, 1),
+            };
+            let pick = |n: usize| (0..n / stride).map(move |i| i * stride + offset);
+            let (_, between) = joint_parent(scene, joints, channel.node);
+            let fold = |child: Transform| between.compose(&child);
+
+            let i = match tracks.iter().position(|t| t.bone == bone) {
+                Some(i) => i,
+                None => {
+                    tracks.push(BoneTrack::new(bone));
+                    tracks.len() - 1
+                }
+            };
+            match &channel.values {
+                GltfChannelValues::Translations(v) => {
+                    tracks[i].translation = Some(Track {
+                        times: channel.times.clone(),
+                        values: pick(v.len())
+                            .map(|k| {
+                                fold(Transform {
+                                    translation: Vector3::from(v[k]),
+                                    ..Default::default()
+                                })
+                                .translation
+                            })
+                            .collect(),
+                        interpolation,
+                    });
+                }
+                GltfChannelValues::Rotations(v) => {
+                    tracks[i].rotation = Some(Track {
+                        times: channel.times.clone(),
+                        values: pick(v.len())
+                            .map(|k| {
+                                fold(Transform {
+                                    rotation: quat_xyzw(v[k]),
+                                    ..Default::default()
+                                })
+                                .rotation
+                            })
+                            .collect(),
+                        interpolation,
+                    });
+                }
+                GltfChannelValues::Scales(v) => {
+                    tracks[i].scale = Some(Track {
+                        times: channel.times.clone(),
+                        values: pick(v.len())
+                            .map(|k| {
+                                fold(Transform {
+                                    scale: Vector3::from(v[k]),
+                                    ..Default::default()
+                                })
+                                .scale
+                            })
+                            .collect(),
+                        interpolation,
+                    });
+                }
+                GltfChannelValues::MorphWeights(_) => {}
+            }
+        }
+        Self::new(&anim.name, tracks)
+    }
+
+    /// Overwrites the animated properties of `pose` with their values at
+    /// `time`, wrapping around the clip if `looping`.
+    pub fn sample(&self, time: f32, looping: bool, pose: &mut Pose) {
+        let time = if looping && self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            time
+        };
+        for track in self.tracks.iter() {
+            let local = match pose.locals.get_mut(track.bone) {
+                Some(l) => l,
+                None => continue,
+            };
+            if let Some(t) = track
+                .translation
+                .as_ref()
+                .and_then(|k| k.sample(time, lerp_vec3))
+            {
+                local.translation = t;
+            }
+            if let Some(r) = track.rotation.as_ref().and_then(|k| k.sample(time, nlerp)) {
+                local.rotation = r;
+            }
+            if let Some(s) = track.scale.as_ref().and_then(|k| k.sample(time, lerp_vec3)) {
+                local.scale = s;
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Playback {
+    clip: usize,
+    time: f32,
+}
+
+/// Plays clips on a skeleton, cross-fading between them.
+pub struct AnimationPlayer {
+    pub clips: Vec<AnimationClip>,
+    pub looping: bool,
+    current: Option<Playback>,
+    fading_from: Option<Playback>,
+    fade_elapsed: f32,
+    fade_duration: f32,
+}
+
+impl AnimationPlayer {
+    pub fn new(clips: Vec<AnimationClip>) -> Self {
+        AnimationPlayer {
+            clips,
+            looping: true,
+            current: None,
+            fading_from: None,
+            fade_elapsed: 0.0,
+            fade_duration: 0.0,
+        }
+    }
+
+    pub fn find_clip(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|c| c.name == name)
+    }
+
+    /// Switches to `clip` immediately.
+    pub fn play(&mut self, clip: usize) {
+        self.current = Some(Playback { clip, time: 0.0 });
+        self.fading_from = None;
+    }
+
+    /// Starts `clip` and blends to it from whatever is playing over
+    /// `duration` seconds.
+    pub fn cross_fade(&mut self, clip: usize, duration: f32) {
+        if duration <= 0.0 || self.current.is_none() {
+            self.play(clip);
+            return;
+        }
+        self.fading_from = self.current;
+        self.current = Some(Playback { clip, time: 0.0 });
+        self.fade_elapsed = 0.0;
+        self.fade_duration = duration;
+    }
+
+    pub fn current_clip(&self) -> Option<usize> {
+        self.current.map(|p| p.clip)
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.fading_from.is_some()
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for p in self.current.iter_mut().chain(self.fading_from.iter_mut()) {
+            p.time += dt;
+        }
+        if self.fading_from.is_some() {
+            self.fade_elapsed += dt;
+            if self.fade_elapsed >= self.fade_duration {
+                self.fading_from = None;
+            }
+        }
+    }
+
+    fn sample_playback(&self, p: Playback, skeleton: &Skeleton) -> Pose {
+        let mut pose = skeleton.rest_pose();
+        if let Some(clip) = self.clips.get(p.clip) {
+            clip.sample(p.time, self.looping, &mut pose);
+        }
+        pose
+    }
+
+    /// The blended pose at the current time; the rest pose if nothing plays.
+    pub fn pose(&self, skeleton: &Skeleton) -> Pose {
+        let current = match self.current {
+            Some(p) => self.sample_playback(p, skeleton),
+            None => return skeleton.rest_pose(),
+        };
+        match self.fading_from {
+            Some(from) => {
+                let t = (self.fade_elapsed / self.fade_duration).min(1.0);
+                self.sample_playback(from, skeleton).blend(&current, t)
+            }
+            None => current,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gltf::{GltfAnimation, GltfChannel, GltfSkin};
+
+    fn close(a: &Matrix4<f32>, b: &Matrix4<f32>) -> bool {
+        (a - b).iter().all(|x| x.abs() < 1e-5)
+    }
+
+    fn offset(x: f32) -> Transform {
+        Transform {
+            translation: Vector3::new(x, 0.0, 0.0),
+            ..Default::default()
+        }
+    }
+
+    /// Shoulder at the origin, elbow and hand one unit apart along +x.
+    /// Children come before their parents to exercise the ordering.
+    fn arm() -> Skeleton {
+        let mut s = Skeleton::new(vec![
+            Bone::new("hand", Some(1), offset(1.0)),
+            Bone::new("elbow", Some(2), offset(1.0)),
+            Bone::new("shoulder", None, Default::default()),
+        ])
+        .unwrap();
+        s.bind_rest_pose();
+        s
+    }
+
+    #[test]
+    fn rejects_bad_hierarchies() {
+        let a = Bone::new("a", Some(1), Default::default());
+        let b = Bone::new("b", Some(0), Default::default());
+        assert_eq!(
+            Skeleton::new(vec![a.clone(), b]).unwrap_err(),
+            SkeletonError::Cycle(0)
+        );
+        let c = Bone::new("c", Some(5), Default::default());
+        assert_eq!(
+            Skeleton::new(vec![a, c]).unwrap_err(),
+            SkeletonError::BadParent(1)
+        );
+    }
+
+    #[test]
+    fn computes_global_and_skinning_matrices() {
+        let s = arm();
+        let rest = s.rest_pose();
+        let globals = rest.global_matrices(&s);
+        assert!(close(
+            &globals[0],
+            &Matrix4::new_translation(&Vector3::new(2.0, 0.0, 0.0))
+        ));
+        for m in rest.skinning_matrices(&s).iter() {
+            assert!(close(m, &Matrix4::identity()));
+        }
+
+        // Bending the shoulder moves the hand around it.
+        let mut pose = s.rest_pose();
+        pose.locals[2].rotation =
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f32::consts::FRAC_PI_2);
+        let hand = pose.global_matrices(&s)[0].transform_point(&nalgebra::Point3::origin());
+        assert!((hand - nalgebra::Point3::new(0.0, 2.0, 0.0)).norm() < 1e-5);
+    }
+
+    fn bounce() -> AnimationClip {
+        let mut track = BoneTrack::new(2);
+        track.translation = Some(Track {
+            times: vec![0.0, 1.0, 2.0],
+            values: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 2.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
+            ],
+            interpolation: Interpolation::Linear,
+        });
+        AnimationClip::new("bounce", vec![track])
+    }
+
+    #[test]
+    fn samples_keyframes() {
+        let s = arm();
+        let clip = bounce();
+        assert_eq!(clip.duration, 2.0);
+
+        let mut pose = s.rest_pose();
+        clip.sample(0.5, false, &mut pose);
+        assert_eq!(pose.locals[2].translation, Vector3::new(0.0, 1.0, 0.0));
+        clip.sample(5.0, false, &mut pose);
+        assert_eq!(pose.locals[2].translation, Vector3::zeros());
+        clip.sample(2.5, true, &mut pose);
+        assert_eq!(pose.locals[2].translation, Vector3::new(0.0, 1.0, 0.0));
+        // Untouched bones keep their rest transform.
+        assert_eq!(pose.locals[1], s.rest_pose().locals[1]);
+
+        let mut step = clip.clone();
+        step.tracks[0].translation.as_mut().unwrap().interpolation = Interpolation::Step;
+        step.sample(0.9, false, &mut pose);
+        assert_eq!(pose.locals[2].translation, Vector3::zeros());
+    }
+
+    #[test]
+    fn folds_armature_nodes_into_gltf_joints() {
+        let node = |name: &str, parent, translation, rotation| GltfNode {
+            name: name.to_string(),
+            parent,
+            children: Vec::new(),
+            translation,
+            rotation,
+            scale: [1.0; 3],
+            mesh: None,
+            skin: None,
+        };
+        // A quarter turn about z, in x, y, z, w order.
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let scene = GltfScene {
+            meshes: Vec::new(),
+            nodes: vec![
+                node("Armature", None, [0.0, 0.0, 5.0], [0.0, 0.0, half, half]),
+                node("root", Some(0), [1.0, 0.0, 0.0], [0.0, 0.0, 0.0, 1.0]),
+                node("tip", Some(1), [1.0, 0.0, 0.0], [0.0, 0.0, 0.0, 1.0]),
+            ],
+            roots: vec![0],
+            skins: vec![GltfSkin {
+                name: String::new(),
+                joints: vec![1, 2],
+                inverse_bind_matrices: vec![
+                    [
+                        [1.0, 0.0, 0.0, 0.0],
+                        [0.0, 1.0, 0.0, 0.0],
+                        [0.0, 0.0, 1.0, 0.0],
+                        [0.0, 0.0, 0.0, 1.0],
+                    ];
+                    2
+                ],
+            }],
+            animations: vec![GltfAnimation {
+                name: "slide".to_string(),
+                channels: vec![GltfChannel {
+                    node: 1,
+                    interpolation: GltfInterpolation::Linear,
+                    times: vec![0.0],
+                    values: GltfChannelValues::Translations(vec![[2.0, 0.0, 0.0]]),
+                }],
+            }],
+            materials: Vec::new(),
+            images: Vec::new(),
+        };
+        assert_eq!(
+            Skeleton::from_gltf(&scene, 1).unwrap_err(),
+            SkeletonError::BadSkin(1)
+        );
+
+        let s = Skeleton::from_gltf(&scene, 0).unwrap();
+        assert_eq!(s.bones()[1].parent, Some(0));
+        let origin = nalgebra::Point3::origin();
+        let tip = s.rest_pose().global_matrices(&s)[1].transform_point(&origin);
+        assert!((tip - nalgebra::Point3::new(0.0, 2.0, 5.0)).norm() < 1e-5);
+
+        let mut pose = s.rest_pose();
+        AnimationClip::from_gltf(&scene, 0, 0).sample(0.0, false, &mut pose);
+        let tip = pose.global_matrices(&s)[1].transform_point(&origin);
+        assert!((tip - nalgebra::Point3::new(0.0, 3.0, 5.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn blends_rotations_along_shortest_arc() {
+        let a = Transform::default();
+        let b = Transform {
+            rotation: UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 1.0),
+            ..Default::default()
+        };
+        // The same rotation with a negated quaternion must blend identically.
+        let mut c = b;
+        c.rotation = UnitQuaternion::new_unchecked(-*b.rotation.quaternion());
+        let ab = a.lerp(&b, 0.5).rotation;
+        let ac = a.lerp(&c, 0.5).rotation;
+        assert!(ab.angle_to(&ac) < 1e-5);
+        assert!((ab.angle() - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cross_fades_between_clips() {
+        let s = arm();
+        let mut still = bounce();
+        still.name = "still".to_string();
+        for v in still.tracks[0]
+            .translation
+            .as_mut()
+            .unwrap()
+            .values
+            .iter_mut()
+        {
+            *v = Vector3::new(4.0, 0.0, 0.0);
+        }
+
+        let mut player = AnimationPlayer::new(vec![bounce(), still]);
+        assert_eq!(player.pose(&s), s.rest_pose());
+        player.play(0);
+        player.update(1.0);
+        assert_eq!(
+            player.pose(&s).locals[2].translation,
+            Vector3::new(0.0, 2.0, 0.0)
+        );
+
+        player.cross_fade(player.find_clip("still").unwrap(), 1.0);
+        player.update(0.5);
+        assert!(player.is_fading());
+        // Halfway between bounce at t=1.5 and still.
+        let t = player.pose(&s).locals[2].translation;
+        assert!((t - Vector3::new(2.0, 0.5, 0.0)).norm() < 1e-5);
+
+        player.update(0.6);
+        assert!(!player.is_fading());
+        assert_eq!(player.current_clip(), Some(1));
+        assert_eq!(
+            player.pose(&s).locals[2].translation,
+            Vector3::new(4.0, 0.0, 0.0)
+        );
+    }
+}
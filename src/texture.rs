@@ -3,6 +3,13 @@ extern crate image;
 
 use gl::types::*;
 use std::ffi::*;
+use std::os::raw::c_char;
+
+pub mod mipmap;
+pub mod readback;
+
+pub use self::mipmap::MipmapFilter;
+pub use self::readback::save_png;
 
 #[macro_export]
 macro_rules! include_png_texture {
@@ -30,7 +37,7 @@ macro_rules! include_tga_texture {
     };
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum FilteringMode {
     Linear,
     Nearest,
@@ -43,6 +50,60 @@ impl FilteringMode {
             FilteringMode::Nearest => gl::NEAREST,
         }
     }
+
+    /// The `TEXTURE_MIN_FILTER` value for filtering within a level this
+    /// way and between levels as `mipmap` says.
+    pub fn min_gl_enum(&self, mipmap: MipmapMode) -> GLuint {
+        match (self, mipmap) {
+            (_, MipmapMode::None) => self.gl_enum(),
+            (FilteringMode::Nearest, MipmapMode::Nearest) => gl::NEAREST_MIPMAP_NEAREST,
+            (FilteringMode::Linear, MipmapMode::Nearest) => gl::LINEAR_MIPMAP_NEAREST,
+            (FilteringMode::Nearest, MipmapMode::Linear) => gl::NEAREST_MIPMAP_LINEAR,
+            (FilteringMode::Linear, MipmapMode::Linear) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+}
+
+/// How minification picks between mipmap levels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MipmapMode {
+    /// Only the base level is read.
+    None,
+    Nearest,
+    /// Blends the two closest levels; trilinear with `Linear` filtering.
+    Linear,
+}
+
+/// From `GL_EXT_texture_filter_anisotropic`, which the `gl` bindings do not
+/// include.
+pub const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+pub const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+/// Whether the current context lists `name` among its extensions.
+pub fn has_gl_extension(name: &str) -> bool {
+    unsafe {
+        let mut n: GLint = 0;
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut n);
+        (0..n.max(0) as GLuint).any(|i| {
+            let ext = gl::GetStringi(gl::EXTENSIONS, i);
+            !ext.is_null() && CStr::from_ptr(ext as *const c_char).to_bytes() == name.as_bytes()
+        })
+    }
+}
+
+/// The most anisotropy the context allows, or `None` if anisotropic
+/// filtering is not available.
+pub fn max_anisotropy() -> Option<f32> {
+    if !has_gl_extension("GL_EXT_texture_filter_anisotropic")
+        && !has_gl_extension("GL_ARB_texture_filter_anisotropic")
+    {
+        return None;
+    }
+    let mut max: GLfloat = 1.0;
+    unsafe {
+        gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max);
+    }
+    Some(max)
 }
 
 #[derive(Copy, Clone)]
@@ -52,12 +113,36 @@ pub enum WrapMode {
     Repeat,
 }
 
-#[derive(Copy, Clone)]
+/// Storage of a texture's pixels. Every color format can be sampled and
+/// attached to a `Framebuffer`.
+///
+/// On GLES 3.0, rendering to the float formats needs
+/// `EXT_color_buffer_float` and linear filtering of `Rgba32F` and `R32F`
+/// needs `OES_texture_float_linear`. Integer formats are read with
+/// `usampler2D`/`isampler2D` and only with `Nearest` filtering.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TextureFormat {
     Invalid,
+    /// 8 bits per channel, unsigned normalized.
     Rgba,
     Depth,
     DepthStencil,
+    R8,
+    Rg8,
+    Rgb8,
+    /// Stored in sRGB, read back as linear.
+    Srgb8Alpha8,
+    Rgba16F,
+    Rgba32F,
+    R32F,
+    /// Packed unsigned floats, for HDR color without alpha.
+    R11G11B10F,
+    R8UI,
+    R32UI,
+    R32I,
+    Rgba8UI,
+    Rgba32UI,
+    Rgba32I,
 }
 
 impl WrapMode {
@@ -70,11 +155,131 @@ impl WrapMode {
     }
 }
 
+impl TextureFormat {
+    /// The sized format GL stores the texture in.
+    pub fn internal_format(self) -> GLenum {
+        match self {
+            TextureFormat::Invalid => gl::NONE,
+            TextureFormat::Rgba => gl::RGBA8,
+            TextureFormat::Depth => gl::DEPTH_COMPONENT32F,
+            TextureFormat::DepthStencil => gl::DEPTH24_STENCIL8,
+            TextureFormat::R8 => gl::R8,
+            TextureFormat::Rg8 => gl::RG8,
+            TextureFormat::Rgb8 => gl::RGB8,
+            TextureFormat::Srgb8Alpha8 => gl::SRGB8_ALPHA8,
+            TextureFormat::Rgba16F => gl::RGBA16F,
+            TextureFormat::Rgba32F => gl::RGBA32F,
+            TextureFormat::R32F => gl::R32F,
+            TextureFormat::R11G11B10F => gl::R11F_G11F_B10F,
+            TextureFormat::R8UI => gl::R8UI,
+            TextureFormat::R32UI => gl::R32UI,
+            TextureFormat::R32I => gl::R32I,
+            TextureFormat::Rgba8UI => gl::RGBA8UI,
+            TextureFormat::Rgba32UI => gl::RGBA32UI,
+            TextureFormat::Rgba32I => gl::RGBA32I,
+        }
+    }
+
+    /// The layout of pixel data passed to and read back from GL.
+    pub fn pixel_format(self) -> GLenum {
+        match self {
+            TextureFormat::Invalid => gl::NONE,
+            TextureFormat::Depth => gl::DEPTH_COMPONENT,
+            TextureFormat::DepthStencil => gl::DEPTH_STENCIL,
+            TextureFormat::R8 | TextureFormat::R32F => gl::RED,
+            TextureFormat::Rg8 => gl::RG,
+            TextureFormat::Rgb8 | TextureFormat::R11G11B10F => gl::RGB,
+            TextureFormat::Rgba
+            | TextureFormat::Srgb8Alpha8
+            | TextureFormat::Rgba16F
+            | TextureFormat::Rgba32F => gl::RGBA,
+            TextureFormat::R8UI | TextureFormat::R32UI | TextureFormat::R32I => gl::RED_INTEGER,
+            TextureFormat::Rgba8UI | TextureFormat::Rgba32UI | TextureFormat::Rgba32I => {
+                gl::RGBA_INTEGER
+            }
+        }
+    }
+
+    /// The component type of pixel data passed to and read back from GL.
+    /// Float formats of every width take `f32`s.
+    pub fn pixel_type(self) -> GLenum {
+        match self {
+            TextureFormat::Invalid => gl::NONE,
+            TextureFormat::DepthStencil => gl::UNSIGNED_INT_24_8,
+            TextureFormat::Depth
+            | TextureFormat::Rgba16F
+            | TextureFormat::Rgba32F
+            | TextureFormat::R32F
+            | TextureFormat::R11G11B10F => gl::FLOAT,
+            TextureFormat::Rgba
+            | TextureFormat::R8
+            | TextureFormat::Rg8
+            | TextureFormat::Rgb8
+            | TextureFormat::Srgb8Alpha8
+            | TextureFormat::R8UI
+            | TextureFormat::Rgba8UI => gl::UNSIGNED_BYTE,
+            TextureFormat::R32UI | TextureFormat::Rgba32UI => gl::UNSIGNED_INT,
+            TextureFormat::R32I | TextureFormat::Rgba32I => gl::INT,
+        }
+    }
+
+    pub fn channels(self) -> usize {
+        match self {
+            TextureFormat::Invalid => 0,
+            TextureFormat::Depth
+            | TextureFormat::R8
+            | TextureFormat::R32F
+            | TextureFormat::R8UI
+            | TextureFormat::R32UI
+            | TextureFormat::R32I => 1,
+            TextureFormat::DepthStencil | TextureFormat::Rg8 => 2,
+            TextureFormat::Rgb8 | TextureFormat::R11G11B10F => 3,
+            TextureFormat::Rgba
+            | TextureFormat::Srgb8Alpha8
+            | TextureFormat::Rgba16F
+            | TextureFormat::Rgba32F
+            | TextureFormat::Rgba8UI
+            | TextureFormat::Rgba32UI
+            | TextureFormat::Rgba32I => 4,
+        }
+    }
+
+    /// Bytes per pixel of data in `pixel_format` and `pixel_type`, which
+    /// may differ from how GL stores it.
+    pub fn pixel_size(self) -> usize {
+        match self.pixel_type() {
+            gl::UNSIGNED_BYTE => self.channels(),
+            gl::UNSIGNED_INT_24_8 => 4,
+            gl::NONE => 0,
+            _ => 4 * self.channels(),
+        }
+    }
+
+    pub fn is_depth(self) -> bool {
+        matches!(self, TextureFormat::Depth | TextureFormat::DepthStencil)
+    }
+
+    pub fn is_color(self) -> bool {
+        !self.is_depth() && self != TextureFormat::Invalid
+    }
+
+    /// Whether shaders read the texture as integers rather than floats.
+    pub fn is_integer(self) -> bool {
+        matches!(self.pixel_format(), gl::RED_INTEGER | gl::RGBA_INTEGER)
+    }
+}
+
 pub struct Texture {
     id: GLuint,
     width: usize,
     height: usize,
-    filtering_mode: FilteringMode,
+    min_filter: FilteringMode,
+    mag_filter: FilteringMode,
+    mipmap_mode: MipmapMode,
+    /// Mipmap levels uploaded or generated, counting the base level.
+    levels: usize,
+    lod_bias: f32,
+    anisotropy: f32,
     wrap_mode: WrapMode,
     format: TextureFormat,
 }
@@ -98,7 +303,12 @@ impl Texture {
                 height,
                 format,
                 wrap_mode: WrapMode::Clamp,
-                filtering_mode: FilteringMode::Nearest,
+                min_filter: FilteringMode::Nearest,
+                mag_filter: FilteringMode::Nearest,
+                mipmap_mode: MipmapMode::None,
+                levels: 1,
+                lod_bias: 0.0,
+                anisotropy: 1.0,
             };
             ret.set_filtering_mode(FilteringMode::Nearest);
             ret.set_wrap_mode(WrapMode::Repeat);
@@ -131,61 +341,82 @@ impl Texture {
         }
     }
 
+    /// Like `new_rgba_from_image`, with every mipmap level built on the
+    /// CPU by `filter` and trilinear filtering turned on.
+    pub fn new_rgba_from_image_with_mipmaps(
+        img: &image::DynamicImage,
+        filter: MipmapFilter,
+    ) -> Self {
+        // flipping vertical because GL is indexed from the bottom.
+        let rgba = image::imageops::flip_vertical(&img.to_rgba());
+        let mut texture = Self::new(
+            rgba.width() as usize,
+            rgba.height() as usize,
+            TextureFormat::Rgba,
+        );
+        texture.upload_mipmaps(&mipmap::mipmap_chain(&rgba, filter));
+        texture.set_filtering_mode(FilteringMode::Linear);
+        texture.set_mipmap_mode(MipmapMode::Linear);
+        texture
+    }
+
     pub fn new_rgba(w: usize, h: usize) -> Self {
-        unsafe {
-            let mut texture = Self::new(w, h, TextureFormat::Rgba);
-            gl::BindTexture(gl::TEXTURE_2D, texture.id);
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::RGBA8 as i32,
-                w as i32,
-                h as i32,
-                0,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
-                std::ptr::null(),
-            );
-            return texture;
-        }
+        Self::new_with_storage(w, h, TextureFormat::Rgba)
     }
 
     pub fn new_depth(w: usize, h: usize) -> Self {
-        let texture = Self::new(w, h, TextureFormat::Depth);
+        Self::new_with_storage(w, h, TextureFormat::Depth)
+    }
+
+    pub fn new_depth_stencil(w: usize, h: usize) -> Self {
+        Self::new_with_storage(w, h, TextureFormat::DepthStencil)
+    }
+
+    /// A texture with uninitialized storage in `format`, for rendering to
+    /// or for filling with `upload_pixels`.
+    pub fn new_with_storage(w: usize, h: usize, format: TextureFormat) -> Self {
+        assert!(
+            format != TextureFormat::Invalid,
+            "cannot allocate an Invalid texture"
+        );
+        let texture = Self::new(w, h, format);
+        texture.tex_image(std::ptr::null());
+        texture
+    }
+
+    /// Replaces the base level with `pixels`, laid out as
+    /// `format().pixel_format()` and `pixel_type()` with rows bottom to top
+    /// and no padding.
+    pub fn upload_pixels<T: Copy>(&mut self, pixels: &[T]) {
+        assert_eq!(
+            std::mem::size_of_val(pixels),
+            self.width * self.height * self.format.pixel_size(),
+            "pixel data does not match the texture size and format"
+        );
         unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, texture.id);
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::DEPTH_COMPONENT32 as i32,
-                w as i32,
-                h as i32,
-                0,
-                gl::DEPTH_COMPONENT,
-                gl::FLOAT,
-                std::ptr::null(),
-            );
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        }
+        self.tex_image(pixels.as_ptr() as *const c_void);
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
         }
-        return texture;
     }
 
-    pub fn new_depth_stencil(w: usize, h: usize) -> Self {
-        let texture = Self::new(w, h, TextureFormat::DepthStencil);
+    fn tex_image(&self, pixels: *const c_void) {
         unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
-                gl::DEPTH24_STENCIL8 as i32,
-                w as i32,
-                h as i32,
+                self.format.internal_format() as i32,
+                self.width as i32,
+                self.height as i32,
                 0,
-                gl::DEPTH_STENCIL,
-                gl::FLOAT,
-                std::ptr::null(),
+                self.format.pixel_format(),
+                self.format.pixel_type(),
+                pixels,
             );
         }
-        return texture;
     }
 
     pub fn id(&self) -> GLuint {
@@ -200,8 +431,34 @@ impl Texture {
         self.height
     }
 
+    /// The magnification filter; see `min_filter` for minification.
     pub fn filtering_mode(&self) -> FilteringMode {
-        self.filtering_mode
+        self.mag_filter
+    }
+
+    pub fn min_filter(&self) -> FilteringMode {
+        self.min_filter
+    }
+
+    pub fn mag_filter(&self) -> FilteringMode {
+        self.mag_filter
+    }
+
+    pub fn mipmap_mode(&self) -> MipmapMode {
+        self.mipmap_mode
+    }
+
+    /// Mipmap levels available, counting the base level.
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    pub fn lod_bias(&self) -> f32 {
+        self.lod_bias
+    }
+
+    pub fn anisotropy(&self) -> f32 {
+        self.anisotropy
     }
 
     pub fn wrap_mode(&self) -> WrapMode {
@@ -212,21 +469,142 @@ impl Texture {
         self.format
     }
 
+    /// Sets both the minification and magnification filter.
     pub fn set_filtering_mode(&mut self, mode: FilteringMode) {
+        self.set_min_filter(mode);
+        self.set_mag_filter(mode);
+    }
+
+    pub fn set_min_filter(&mut self, mode: FilteringMode) {
+        self.min_filter = mode;
+        self.apply_min_filter();
+    }
+
+    pub fn set_mag_filter(&mut self, mode: FilteringMode) {
         unsafe {
             gl::BindTexture(gl::TEXTURE_2D, self.id);
             gl::TexParameteri(
                 gl::TEXTURE_2D,
-                gl::TEXTURE_MIN_FILTER,
+                gl::TEXTURE_MAG_FILTER,
                 mode.gl_enum() as GLint,
             );
+        }
+        self.mag_filter = mode;
+    }
+
+    /// Takes effect once the texture has mipmaps; until then only the base
+    /// level is read, since sampling missing levels would give black.
+    pub fn set_mipmap_mode(&mut self, mode: MipmapMode) {
+        self.mipmap_mode = mode;
+        self.apply_min_filter();
+    }
+
+    fn apply_min_filter(&self) {
+        let mipmap = if self.levels > 1 {
+            self.mipmap_mode
+        } else {
+            MipmapMode::None
+        };
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
             gl::TexParameteri(
                 gl::TEXTURE_2D,
-                gl::TEXTURE_MAG_FILTER,
-                mode.gl_enum() as GLint,
+                gl::TEXTURE_MIN_FILTER,
+                self.min_filter.min_gl_enum(mipmap) as GLint,
+            );
+        }
+    }
+
+    /// Added to the mipmap level the GPU picks; positive values blur,
+    /// negative values sharpen and shimmer.
+    ///
+    /// Desktop GL only: GLES 3.0 and WebGL 2 can only bias in the shader.
+    #[cfg(not(target_os = "emscripten"))]
+    pub fn set_lod_bias(&mut self, bias: f32) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::TexParameterf(gl::TEXTURE_2D, gl::TEXTURE_LOD_BIAS, bias);
+        }
+        self.lod_bias = bias;
+    }
+
+    /// Requests up to `amount` samples along the direction a surface
+    /// recedes in, clamped to what the context allows. Returns the amount
+    /// applied, 1.0 without the anisotropic filtering extension.
+    pub fn set_anisotropy(&mut self, amount: f32) -> f32 {
+        let max = match max_anisotropy() {
+            Some(max) => max,
+            None => return self.anisotropy,
+        };
+        let amount = amount.max(1.0).min(max);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::TexParameterf(gl::TEXTURE_2D, TEXTURE_MAX_ANISOTROPY, amount);
+        }
+        self.anisotropy = amount;
+        amount
+    }
+
+    /// Builds every mipmap level from the base level on the GPU.
+    pub fn generate_mipmaps(&mut self) {
+        self.levels = mipmap::mip_levels(self.width, self.height);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_MAX_LEVEL,
+                self.levels as GLint - 1,
             );
         }
-        self.filtering_mode = mode;
+        self.apply_min_filter();
+    }
+
+    /// Uploads `levels` as the base level and successive mipmaps, e.g.
+    /// from `mipmap::mipmap_chain`. Each level must be half the size of the
+    /// one before, rounding down to at least 1, starting at the texture's
+    /// size. The texture must be `Rgba` or `Srgb8Alpha8`.
+    pub fn upload_mipmaps(&mut self, levels: &[image::RgbaImage]) {
+        assert!(!levels.is_empty(), "no mipmap levels to upload");
+        assert!(
+            matches!(
+                self.format,
+                TextureFormat::Rgba | TextureFormat::Srgb8Alpha8
+            ),
+            "mipmaps can only be uploaded to 8-bit RGBA textures"
+        );
+        for (level, img) in levels.iter().enumerate() {
+            let (w, h) = mipmap::mip_size(self.width, self.height, level);
+            assert_eq!(
+                (img.width() as usize, img.height() as usize),
+                (w, h),
+                "mipmap level {} has the wrong size",
+                level
+            );
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D, self.id);
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    level as GLint,
+                    self.format.internal_format() as GLint,
+                    w as i32,
+                    h as i32,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    img.as_ptr() as *const c_void,
+                );
+            }
+        }
+        self.levels = levels.len();
+        unsafe {
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_MAX_LEVEL,
+                self.levels as GLint - 1,
+            );
+        }
+        self.apply_min_filter();
     }
 
     pub fn set_wrap_mode(&mut self, mode: WrapMode) {
@@ -246,3 +624,48 @@ impl Texture {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_formats_consistently() {
+        let formats = [
+            TextureFormat::Rgba,
+            TextureFormat::Depth,
+            TextureFormat::DepthStencil,
+            TextureFormat::R8,
+            TextureFormat::Rg8,
+            TextureFormat::Rgb8,
+            TextureFormat::Srgb8Alpha8,
+            TextureFormat::Rgba16F,
+            TextureFormat::Rgba32F,
+            TextureFormat::R32F,
+            TextureFormat::R11G11B10F,
+            TextureFormat::R8UI,
+            TextureFormat::R32UI,
+            TextureFormat::R32I,
+            TextureFormat::Rgba8UI,
+            TextureFormat::Rgba32UI,
+            TextureFormat::Rgba32I,
+        ];
+        for &f in formats.iter() {
+            assert_ne!(f.internal_format(), gl::NONE, "{:?}", f);
+            assert!(
+                f.pixel_size() >= f.channels() && f.channels() > 0,
+                "{:?}",
+                f
+            );
+            assert!(f.is_color() != f.is_depth(), "{:?}", f);
+            // Integer storage must be fed integer pixel data, and only it.
+            let int_type = matches!(f.pixel_type(), gl::INT | gl::UNSIGNED_INT);
+            assert!(!int_type || f.is_integer(), "{:?}", f);
+        }
+        assert!(TextureFormat::Rgba8UI.is_integer() && !TextureFormat::Rgba.is_integer());
+        assert_eq!(TextureFormat::Rgb8.pixel_size(), 3);
+        assert_eq!(TextureFormat::R11G11B10F.pixel_size(), 12);
+        assert_eq!(TextureFormat::Rgba32I.pixel_size(), 16);
+        assert!(!TextureFormat::Invalid.is_color());
+    }
+}
@@ -0,0 +1,107 @@
+use image::{FilterType, Rgba, RgbaImage};
+
+/// How `mipmap_chain` shrinks each level.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MipmapFilter {
+    /// Averages 2x2 blocks of the level above, like `glGenerateMipmap`
+    /// usually does. Fast but a little soft.
+    Box,
+    /// Resamples the full image with a Lanczos3 kernel for every level.
+    /// Keeps more detail, at the cost of some ringing near hard edges.
+    Lanczos,
+}
+
+/// Levels in a full mipmap chain, from `width` x `height` down to 1x1.
+pub fn mip_levels(width: usize, height: usize) -> usize {
+    let mut size = width.max(height).max(1);
+    let mut levels = 1;
+    while size > 1 {
+        size /= 2;
+        levels += 1;
+    }
+    levels
+}
+
+/// Size of `level` in a chain starting at `width` x `height`.
+pub fn mip_size(width: usize, height: usize, level: usize) -> (usize, usize) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+/// `base` followed by every smaller level down to 1x1, ready for
+/// `Texture::upload_mipmaps`.
+pub fn mipmap_chain(base: &RgbaImage, filter: MipmapFilter) -> Vec<RgbaImage> {
+    let (width, height) = (base.width() as usize, base.height() as usize);
+    let mut chain = vec![base.clone()];
+    for level in 1..mip_levels(width, height) {
+        let (w, h) = mip_size(width, height, level);
+        let next = match filter {
+            MipmapFilter::Box => box_downsample(chain.last().unwrap(), w, h),
+            MipmapFilter::Lanczos => {
+                image::imageops::resize(base, w as u32, h as u32, FilterType::Lanczos3)
+            }
+        };
+        chain.push(next);
+    }
+    chain
+}
+
+/// Each output pixel averages the 2x2 block it covers, or 2x1 along an
+/// axis that is already 1 pixel. A trailing odd row or column is dropped.
+fn box_downsample(src: &RgbaImage, width: usize, height: usize) -> RgbaImage {
+    let (sw, sh) = src.dimensions();
+    RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+        let xs = [2 * x, (2 * x + 1).min(sw - 1)];
+        let ys = [2 * y, (2 * y + 1).min(sh - 1)];
+        let mut sum = [0u32; 4];
+        for &sy in ys.iter() {
+            for &sx in xs.iter() {
+                let p = src.get_pixel(sx, sy);
+                for (s, &c) in sum.iter_mut().zip(p.data.iter()) {
+                    *s += c as u32;
+                }
+            }
+        }
+        Rgba([
+            ((sum[0] + 2) / 4) as u8,
+            ((sum[1] + 2) / 4) as u8,
+            ((sum[2] + 2) / 4) as u8,
+            ((sum[3] + 2) / 4) as u8,
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_full_chains() {
+        assert_eq!(mip_levels(1, 1), 1);
+        assert_eq!(mip_levels(8, 4), 4);
+        assert_eq!(mip_levels(5, 3), 3);
+        assert_eq!(mip_size(8, 4, 3), (1, 1));
+
+        // A checkerboard averages to grey with either filter.
+        let checker = RgbaImage::from_fn(8, 4, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            Rgba([v, v, v, 255])
+        });
+        for &filter in [MipmapFilter::Box, MipmapFilter::Lanczos].iter() {
+            let chain = mipmap_chain(&checker, filter);
+            let sizes: Vec<_> = chain.iter().map(|l| l.dimensions()).collect();
+            assert_eq!(sizes, vec![(8, 4), (4, 2), (2, 1), (1, 1)]);
+            for level in chain[1..].iter() {
+                for p in level.pixels() {
+                    assert!((p[0] as i32 - 128).abs() <= 8, "{:?} {:?}", filter, p);
+                    assert!(p[3] >= 250);
+                }
+            }
+        }
+
+        let odd = RgbaImage::from_fn(3, 1, |x, _| Rgba([x as u8 * 100, 0, 0, 0]));
+        assert_eq!(
+            mipmap_chain(&odd, MipmapFilter::Box)[1].get_pixel(0, 0)[0],
+            50
+        );
+    }
+}
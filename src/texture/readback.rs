@@ -0,0 +1,225 @@
+use super::{Texture, TextureFormat};
+use gl::types::*;
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, ImageResult};
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Pixels as `glReadPixels` returns them, bottom row first.
+enum Pixels {
+    Bytes(Vec<u8>),
+    Floats(Vec<f32>),
+    Uints(Vec<u32>),
+    Ints(Vec<i32>),
+}
+
+impl Pixels {
+    /// Squeezes every component into a byte: floats are clamped to [0, 1]
+    /// and scaled, integers are clamped to 0..=255.
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Pixels::Bytes(b) => b,
+            Pixels::Floats(f) => f
+                .iter()
+                .map(|&c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect(),
+            Pixels::Uints(u) => u.iter().map(|&c| c.min(255) as u8).collect(),
+            Pixels::Ints(i) => i.iter().map(|&c| c.clamp(0, 255) as u8).collect(),
+        }
+    }
+}
+
+/// The pixel format, type and components per pixel to read `format` with.
+/// GLES 3.0 only promises four-component reads of color buffers, so colors
+/// always come back as RGBA.
+fn read_layout(format: TextureFormat) -> (GLenum, GLenum, usize) {
+    match format {
+        TextureFormat::Depth => (gl::DEPTH_COMPONENT, gl::FLOAT, 1),
+        TextureFormat::DepthStencil => (gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8, 1),
+        f if f.is_integer() && f.pixel_type() == gl::INT => (gl::RGBA_INTEGER, gl::INT, 4),
+        f if f.is_integer() => (gl::RGBA_INTEGER, gl::UNSIGNED_INT, 4),
+        f if f.pixel_type() == gl::FLOAT => (gl::RGBA, gl::FLOAT, 4),
+        _ => (gl::RGBA, gl::UNSIGNED_BYTE, 4),
+    }
+}
+
+/// Converts `pixels` read with `read_layout(format)` to an image, top row
+/// first. Depth comes out as grey and two-channel formats as red and green
+/// with no blue.
+fn to_image(format: TextureFormat, width: usize, height: usize, pixels: Pixels) -> DynamicImage {
+    // A minimized window or an empty texture has nothing to read.
+    if width == 0 || height == 0 {
+        return DynamicImage::new_rgba8(width as u32, height as u32);
+    }
+    let pixels = match (format, pixels) {
+        // Depth is in the top 24 bits, stencil in the bottom 8.
+        (TextureFormat::DepthStencil, Pixels::Uints(u)) => Pixels::Floats(
+            u.iter()
+                .map(|&d| (d >> 8) as f32 / 0x00ff_ffff as f32)
+                .collect(),
+        ),
+        (_, pixels) => pixels,
+    };
+    let (_, _, stride) = read_layout(format);
+    let channels = if format.is_depth() {
+        1
+    } else {
+        format.channels()
+    };
+    let out_channels = if channels == 2 { 3 } else { channels };
+
+    let bytes = pixels.into_bytes();
+    let mut out = Vec::with_capacity(width * height * out_channels);
+    for row in bytes.chunks(width * stride).take(height).rev() {
+        for pixel in row.chunks(stride) {
+            out.extend_from_slice(&pixel[..channels]);
+            if channels == 2 {
+                out.push(0);
+            }
+        }
+    }
+
+    let (w, h) = (width as u32, height as u32);
+    match out_channels {
+        1 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(w, h, out).unwrap()),
+        3 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(w, h, out).unwrap()),
+        _ => DynamicImage::ImageRgba8(ImageBuffer::from_raw(w, h, out).unwrap()),
+    }
+}
+
+/// Reads a `width` x `height` image in `format` from the read buffer of the
+/// bound read framebuffer.
+pub(crate) fn read_framebuffer(format: TextureFormat, width: usize, height: usize) -> DynamicImage {
+    let (pixel_format, pixel_type, stride) = read_layout(format);
+    let n = width * height * stride;
+    let mut pixels = match pixel_type {
+        gl::UNSIGNED_BYTE => Pixels::Bytes(vec![0; n]),
+        gl::FLOAT => Pixels::Floats(vec![0.0; n]),
+        gl::INT => Pixels::Ints(vec![0; n]),
+        _ => Pixels::Uints(vec![0; n]),
+    };
+    let ptr = match &mut pixels {
+        Pixels::Bytes(b) => b.as_mut_ptr() as *mut c_void,
+        Pixels::Floats(f) => f.as_mut_ptr() as *mut c_void,
+        Pixels::Uints(u) => u.as_mut_ptr() as *mut c_void,
+        Pixels::Ints(i) => i.as_mut_ptr() as *mut c_void,
+    };
+    unsafe {
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as GLsizei,
+            height as GLsizei,
+            pixel_format,
+            pixel_type,
+            ptr,
+        );
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+    }
+    to_image(format, width, height, pixels)
+}
+
+/// Writes `image` to `path` as a PNG, whatever the extension.
+pub fn save_png(image: &DynamicImage, path: &Path) -> ImageResult<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    image.write_to(&mut file, ImageOutputFormat::PNG)
+}
+
+impl Texture {
+    /// Reads the base level back, top row first, so an image passed to
+    /// `new_rgba_from_image` comes back the way it went in.
+    ///
+    /// Float components are clamped to [0, 1] and integer ones to 0..=255.
+    /// Depth formats come back as grey, but only on desktop GL; GLES cannot
+    /// read depth.
+    pub fn read_pixels(&self) -> DynamicImage {
+        let attachment = match self.format {
+            TextureFormat::Depth => gl::DEPTH_ATTACHMENT,
+            TextureFormat::DepthStencil => gl::DEPTH_STENCIL_ATTACHMENT,
+            _ => gl::COLOR_ATTACHMENT0,
+        };
+        unsafe {
+            let mut previous: GLint = 0;
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous);
+            let mut fbo: GLuint = 0;
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(gl::READ_FRAMEBUFFER, attachment, gl::TEXTURE_2D, self.id, 0);
+            gl::ReadBuffer(if self.format.is_color() {
+                gl::COLOR_ATTACHMENT0
+            } else {
+                gl::NONE
+            });
+
+            let image = read_framebuffer(self.format, self.width, self.height);
+
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous as GLuint);
+            gl::DeleteFramebuffers(1, &fbo);
+            image
+        }
+    }
+
+    pub fn save_png(&self, path: &Path) -> ImageResult<()> {
+        save_png(&self.read_pixels(), path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_read_pixels_to_images() {
+        // Two rows of two pixels, bottom row first as GL returns them.
+        let bytes = vec![
+            1, 2, 3, 4, 5, 6, 7, 8, //
+            9, 10, 11, 12, 13, 14, 15, 16,
+        ];
+        let rgba = to_image(TextureFormat::Rgba, 2, 2, Pixels::Bytes(bytes.clone()));
+        assert_eq!(
+            rgba.raw_pixels(),
+            vec![9, 10, 11, 12, 13, 14, 15, 16, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+
+        let rg = to_image(TextureFormat::Rg8, 2, 2, Pixels::Bytes(bytes));
+        match &rg {
+            DynamicImage::ImageRgb8(_) => {}
+            _ => panic!("RG should come back as RGB"),
+        }
+        assert_eq!(rg.raw_pixels(), vec![9, 10, 0, 13, 14, 0, 1, 2, 0, 5, 6, 0]);
+
+        let floats = vec![0.5, -1.0, 2.0, 1.0];
+        let r = to_image(TextureFormat::R32F, 1, 1, Pixels::Floats(floats));
+        assert_eq!(r.raw_pixels(), vec![128]);
+
+        let ints = vec![-5, 300, 7, 0];
+        let i = to_image(TextureFormat::Rgba32I, 1, 1, Pixels::Ints(ints));
+        assert_eq!(i.raw_pixels(), vec![0, 255, 7, 0]);
+
+        let depth = to_image(
+            TextureFormat::DepthStencil,
+            1,
+            1,
+            Pixels::Uints(vec![0xffff_ff07]),
+        );
+        assert_eq!(depth.raw_pixels(), vec![255]);
+
+        let empty = to_image(TextureFormat::Rgba, 0, 4, Pixels::Bytes(Vec::new()));
+        assert_eq!(empty.raw_pixels(), Vec::<u8>::new());
+
+        assert_eq!(
+            read_layout(TextureFormat::R8UI),
+            (gl::RGBA_INTEGER, gl::UNSIGNED_INT, 4)
+        );
+        assert_eq!(
+            read_layout(TextureFormat::R11G11B10F),
+            (gl::RGBA, gl::FLOAT, 4)
+        );
+        assert_eq!(
+            read_layout(TextureFormat::Srgb8Alpha8),
+            (gl::RGBA, gl::UNSIGNED_BYTE, 4)
+        );
+    }
+}
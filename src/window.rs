@@ -1,6 +1,8 @@
 use sdl2::video::GLProfile;
 use std::ffi::CStr;
 use crate::framebuffer::Framebuffer;
+use crate::texture::{readback, TextureFormat};
+use std::path::Path;
 
 pub struct Window {
     gl_context: sdl2::video::GLContext,
@@ -36,6 +38,17 @@ impl Window {
         #[cfg(not(target_os = "emscripten"))]
         let _gl = gl::load_with(|name| sdl_video.gl_get_proc_address(name) as *const _);
 
+        // Instanced attributes are core from GL 3.3; a 3.2 context may only
+        // have them through GL_ARB_instanced_arrays.
+        #[cfg(not(target_os = "emscripten"))]
+        {
+            if !gl::VertexAttribDivisor::is_loaded() {
+                gl::VertexAttribDivisor::load_with(|_| {
+                    sdl_video.gl_get_proc_address("glVertexAttribDivisorARB") as *const _
+                });
+            }
+        }
+
         dbg!(unsafe { CStr::from_ptr(gl::GetString(gl::VERSION) as *const i8) });
         dbg!(unsafe { CStr::from_ptr(gl::GetString(gl::SHADING_LANGUAGE_VERSION) as *const i8) });
 
@@ -69,6 +82,26 @@ impl Window {
         }
     }
 
+    /// Reads what has been drawn to the window since the last swap, top
+    /// row first. Call it before `swap_buffers`.
+    pub fn read_pixels(&self) -> image::DynamicImage {
+        let (w, h) = self.sdl_window.drawable_size();
+        unsafe {
+            let mut previous: gl::types::GLint = 0;
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            gl::ReadBuffer(gl::BACK);
+            let image = readback::read_framebuffer(TextureFormat::Rgba, w as usize, h as usize);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous as gl::types::GLuint);
+            image
+        }
+    }
+
+    /// Saves the window's contents as a PNG, e.g. for a screenshot.
+    pub fn save_png(&self, path: &Path) -> image::ImageResult<()> {
+        readback::save_png(&self.read_pixels(), path)
+    }
+
     pub fn swap_buffers(&self) {
         Framebuffer::unbind();
         self.sdl_window.gl_swap_window();